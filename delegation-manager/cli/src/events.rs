@@ -0,0 +1,158 @@
+use anchor_client::anchor_lang::{AnchorDeserialize, Discriminator};
+use solana_sdk::pubkey::Pubkey;
+use upl_delegation_manager::{
+    DelegationCancelled, DelegationConfirmed, DelegationCreated, DelegationDeclined,
+    MasterTransferred, RepresentativeRotated, SessionClosed,
+};
+
+const PROGRAM_DATA: &str = "Program data: ";
+
+/// Lifecycle event emitted by the Delegation Manager program
+pub enum DelegationEvent {
+    Created(DelegationCreated),
+    Confirmed(DelegationConfirmed),
+    Cancelled(DelegationCancelled),
+    Declined(DelegationDeclined),
+    MasterTransferred(MasterTransferred),
+    RepresentativeRotated(RepresentativeRotated),
+    SessionClosed(SessionClosed),
+}
+
+impl DelegationEvent {
+    /// Decodes an event from the data logged by emit!, prefixed by its discriminator
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        if data.len() < 8 {
+            return None;
+        }
+        let (discriminator, mut data) = data.split_at(8);
+
+        if discriminator == DelegationCreated::discriminator() {
+            DelegationCreated::deserialize(&mut data)
+                .ok()
+                .map(Self::Created)
+        } else if discriminator == DelegationConfirmed::discriminator() {
+            DelegationConfirmed::deserialize(&mut data)
+                .ok()
+                .map(Self::Confirmed)
+        } else if discriminator == DelegationCancelled::discriminator() {
+            DelegationCancelled::deserialize(&mut data)
+                .ok()
+                .map(Self::Cancelled)
+        } else if discriminator == DelegationDeclined::discriminator() {
+            DelegationDeclined::deserialize(&mut data)
+                .ok()
+                .map(Self::Declined)
+        } else if discriminator == MasterTransferred::discriminator() {
+            MasterTransferred::deserialize(&mut data)
+                .ok()
+                .map(Self::MasterTransferred)
+        } else if discriminator == RepresentativeRotated::discriminator() {
+            RepresentativeRotated::deserialize(&mut data)
+                .ok()
+                .map(Self::RepresentativeRotated)
+        } else if discriminator == SessionClosed::discriminator() {
+            SessionClosed::deserialize(&mut data)
+                .ok()
+                .map(Self::SessionClosed)
+        } else {
+            None
+        }
+    }
+
+    /// Decodes the events logged by the given program in a transaction's log messages,
+    /// skipping data logged by the other programs it invokes or is invoked by
+    pub fn parse_logs(program_id: &Pubkey, logs: &[String]) -> Vec<Self> {
+        let program_id = program_id.to_string();
+        let mut invocations: Vec<&str> = vec![];
+        let mut events = vec![];
+
+        for log in logs {
+            if let Some(data) = log.strip_prefix(PROGRAM_DATA) {
+                if invocations.last() == Some(&program_id.as_str()) {
+                    if let Some(event) = base64::decode(data)
+                        .ok()
+                        .and_then(|data| Self::decode(&data))
+                    {
+                        events.push(event);
+                    }
+                }
+            } else if let Some(invoked) = log
+                .strip_prefix("Program ")
+                .and_then(|log| log.split_once(" invoke ["))
+                .map(|(invoked, _)| invoked)
+            {
+                invocations.push(invoked);
+            } else if log.starts_with("Program ")
+                && (log.ends_with(" success") || log.contains(" failed: "))
+            {
+                invocations.pop();
+            }
+        }
+
+        events
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Created(_) => "created",
+            Self::Confirmed(_) => "confirmed",
+            Self::Cancelled(_) => "cancelled",
+            Self::Declined(_) => "declined",
+            Self::MasterTransferred(_) => "master transferred",
+            Self::RepresentativeRotated(_) => "representative rotated",
+            Self::SessionClosed(_) => "session closed",
+        }
+    }
+
+    pub fn delegation(&self) -> Pubkey {
+        match self {
+            Self::Created(event) => event.delegation,
+            Self::Confirmed(event) => event.delegation,
+            Self::Cancelled(event) => event.delegation,
+            Self::Declined(event) => event.delegation,
+            Self::MasterTransferred(event) => event.delegation,
+            Self::RepresentativeRotated(event) => event.delegation,
+            Self::SessionClosed(event) => event.delegation,
+        }
+    }
+
+    pub fn slot(&self) -> u64 {
+        match self {
+            Self::Created(event) => event.slot,
+            Self::Confirmed(event) => event.slot,
+            Self::Cancelled(event) => event.slot,
+            Self::Declined(event) => event.slot,
+            Self::MasterTransferred(event) => event.slot,
+            Self::RepresentativeRotated(event) => event.slot,
+            Self::SessionClosed(event) => event.slot,
+        }
+    }
+
+    pub fn details(&self) -> String {
+        match self {
+            Self::Created(event) => format!(
+                "master: {}\nrepresentative: {}\nnamespace: {}\nparent: {}\nrequested: {}",
+                event.master,
+                event.representative,
+                format_optional_pubkey(event.namespace),
+                format_optional_pubkey(event.parent),
+                event.requested
+            ),
+            Self::Confirmed(event) => format!("confirmed by: {}", event.confirmed_by),
+            Self::Cancelled(event) => format!("cancelled by: {}", event.cancelled_by),
+            Self::Declined(event) => format!("declined by: {}", event.representative),
+            Self::MasterTransferred(event) => {
+                format!("from: {}\nto: {}", event.master, event.new_master)
+            }
+            Self::RepresentativeRotated(event) => format!(
+                "from: {}\nto: {}\nnew delegation: {}",
+                event.representative, event.new_representative, event.new_delegation
+            ),
+            Self::SessionClosed(event) => format!("session key: {}", event.representative),
+        }
+    }
+}
+
+fn format_optional_pubkey(pubkey: Option<Pubkey>) -> String {
+    pubkey.map_or_else(|| String::from("-"), |pubkey| pubkey.to_string())
+}
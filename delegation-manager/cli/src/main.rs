@@ -1,41 +1,91 @@
 mod config;
-use anchor_client::anchor_lang::{solana_program, AnchorSerialize, Discriminator};
+mod events;
+use anchor_client::anchor_lang::{
+    solana_program, AccountDeserialize, AnchorSerialize, Discriminator,
+};
 use config::Config;
+use events::DelegationEvent;
 
 use clap::{
     crate_description, crate_name, crate_version, App, AppSettings, Arg, ArgMatches, SubCommand,
 };
 
-use fastcmp::Compare;
 use prettytable::{cell, row, Table};
+use solana_account_decoder::UiAccountEncoding;
 use solana_clap_utils::{
     fee_payer::fee_payer_arg,
-    input_parsers::{pubkey_of_signer, value_of},
-    input_validators::{is_url_or_moniker, is_valid_pubkey},
+    input_parsers::{pubkey_of_signer, pubkeys_of_multiple_signers, value_of, values_of},
+    input_validators::{is_url_or_moniker, is_valid_pubkey, is_valid_signer},
+};
+use solana_client::{
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, RpcFilterType},
 };
 use solana_remote_wallet::remote_wallet::RemoteWalletManager;
 use solana_sdk::{
-    borsh::try_from_slice_unchecked,
+    ed25519_program,
     instruction::{AccountMeta, Instruction},
     message::Message,
+    packet::PACKET_DATA_SIZE,
     pubkey::Pubkey,
-    signature::Signer,
-    system_program,
+    signature::{Signature, Signer},
+    system_program, sysvar,
     transaction::Transaction,
 };
-use std::{str::FromStr, sync::Arc};
+use solana_transaction_status::UiTransactionEncoding;
+use std::{
+    str::FromStr,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 use strum_macros::{EnumString, IntoStaticStr};
-use upl_delegation_manager::{get_delegation_address, Delegation};
+use upl_delegation_manager::{
+    get_confirmation_message, get_delegation_address, get_master_index_address,
+    get_master_settings_address, get_master_transfer_address, get_namespaced_delegation_address,
+    get_representative_index_address, get_role_address, get_sub_delegation_address, Delegation,
+    DelegationMetadata, DelegationTerms, MasterIndex, RepresentativeIndex, Role, MAX_INDEX_ENTRIES,
+    PERMISSION_ALL,
+};
 
 pub(crate) type Error = Box<dyn std::error::Error + Send + Sync>;
 
+/// Maximum number of accounts the RPC returns in a single getMultipleAccounts call
+const MAX_MULTIPLE_ACCOUNTS: usize = 100;
+
+/// Maximum number of Delegations transferred by a single accept_master_transfer transaction
+const MAX_TRANSFERS_PER_TRANSACTION: usize = 8;
+
 #[derive(Debug, Clone, Copy, PartialEq, EnumString, IntoStaticStr)]
 #[strum(serialize_all = "kebab-case")]
 pub enum CommandName {
     Initialize,
     Confirm,
+    SignConfirmation,
+    ConfirmWithSignature,
+    Decline,
     Cancel,
+    CancelAll,
+    Rotate,
+    SetRotationAllowed,
+    Migrate,
+    CreateSession,
+    CloseSession,
+    Request,
+    Approve,
+    UpdateMetadata,
+    CreateRole,
+    UpdateRole,
+    CloseRole,
+    AssignRole,
+    UnassignRole,
+    PauseAll,
+    ResumeAll,
+    RevokeAll,
+    ProposeMasterTransfer,
+    AcceptMasterTransfer,
+    CloseMasterTransfer,
     GetDelegations,
+    GetEvents,
 }
 
 pub fn app<'a, 'b>() -> App<'a, 'b> {
@@ -75,9 +125,23 @@ pub fn app<'a, 'b>() -> App<'a, 'b> {
                         .value_name("REPRESENTATIVE")
                         .validator(is_valid_pubkey)
                         .takes_value(true)
+                        .multiple(true)
                         .index(1)
                         .help(
-                            "Specify the delegation representative. \
+                            "Specify the delegation representatives, delegating to each \
+                            of them under the same terms. These must be valid public keys.",
+                        ),
+                )
+                .args(&delegation_terms_args())
+                .args(&delegation_metadata_args())
+                .arg(
+                    Arg::with_name("namespace")
+                        .long("namespace")
+                        .value_name("NAMESPACE")
+                        .validator(is_valid_pubkey)
+                        .takes_value(true)
+                        .help(
+                            "Derive the delegation from the given protocol namespace. \
                             This must be a valid public key.",
                         ),
                 ),
@@ -97,6 +161,61 @@ pub fn app<'a, 'b>() -> App<'a, 'b> {
                         ),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name(CommandName::SignConfirmation.into())
+                .about("Sign the confirmation of a Delegation off-chain, to be submitted by anyone")
+                .arg(
+                    Arg::with_name("delegation")
+                        .value_name("DELEGATION")
+                        .validator(is_valid_pubkey)
+                        .takes_value(true)
+                        .index(1)
+                        .help(
+                            "Specify the delegation to confirm. \
+                            This must be a valid public key.",
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(CommandName::ConfirmWithSignature.into())
+                .about("Confirm Delegation with the representative's off-chain signature")
+                .arg(
+                    Arg::with_name("delegation")
+                        .value_name("DELEGATION")
+                        .validator(is_valid_pubkey)
+                        .takes_value(true)
+                        .index(1)
+                        .help(
+                            "Specify the delegation to confirm. \
+                            This must be a valid public key.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("signature")
+                        .value_name("SIGNATURE")
+                        .takes_value(true)
+                        .index(2)
+                        .help(
+                            "Specify the representative's signature returned by \
+                            sign-confirmation.",
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(CommandName::Decline.into())
+                .about("Decline Delegation")
+                .arg(
+                    Arg::with_name("delegation")
+                        .value_name("DELEGATION")
+                        .validator(is_valid_pubkey)
+                        .takes_value(true)
+                        .index(1)
+                        .help(
+                            "Specify the delegation to decline. \
+                            This must be a valid public key.",
+                        ),
+                ),
+        )
         .subcommand(
             SubCommand::with_name(CommandName::Cancel.into())
                 .about("Cancel Delegation")
@@ -112,6 +231,310 @@ pub fn app<'a, 'b>() -> App<'a, 'b> {
                         ),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name(CommandName::CancelAll.into())
+                .about("Cancel every Delegation of the owner as master or as representative")
+                .arg(
+                    Arg::with_name("as")
+                        .long("as")
+                        .value_name("ROLE")
+                        .possible_values(&["master", "repr"])
+                        .takes_value(true)
+                        .required(true)
+                        .help(
+                            "Specify whether to cancel the delegations the owner is the master \
+                            of, or the ones it represents.",
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(CommandName::Rotate.into())
+                .about("Rotate the representative of a Delegation to a new key")
+                .arg(
+                    Arg::with_name("delegation")
+                        .value_name("DELEGATION")
+                        .validator(is_valid_pubkey)
+                        .takes_value(true)
+                        .index(1)
+                        .help(
+                            "Specify the delegation to rotate. \
+                            This must be a valid public key.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("new_representative")
+                        .value_name("NEW_REPRESENTATIVE_KEYPAIR")
+                        .validator(is_valid_signer)
+                        .takes_value(true)
+                        .index(2)
+                        .help("Specify the keypair of the new representative."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(CommandName::SetRotationAllowed.into())
+                .about("Allow or forbid rotations of the representative without the master")
+                .arg(
+                    Arg::with_name("delegation")
+                        .value_name("DELEGATION")
+                        .validator(is_valid_pubkey)
+                        .takes_value(true)
+                        .index(1)
+                        .help(
+                            "Specify the delegation to update. \
+                            This must be a valid public key.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("allowed")
+                        .value_name("BOOL")
+                        .possible_values(&["true", "false"])
+                        .takes_value(true)
+                        .index(2)
+                        .help("Specify whether the representative may rotate on its own."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(CommandName::Migrate.into())
+                .about("Migrate Delegation to the current layout")
+                .arg(
+                    Arg::with_name("delegation")
+                        .value_name("DELEGATION")
+                        .validator(is_valid_pubkey)
+                        .takes_value(true)
+                        .index(1)
+                        .help(
+                            "Specify the legacy delegation to migrate. \
+                            This must be a valid public key.",
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(CommandName::CreateSession.into())
+                .about("Delegate to an ephemeral session key, confirmed right away")
+                .arg(
+                    Arg::with_name("session_key")
+                        .value_name("SESSION_KEY")
+                        .validator(is_valid_pubkey)
+                        .takes_value(true)
+                        .index(1)
+                        .help(
+                            "Specify the session key generated by the owner. \
+                            This must be a valid public key.",
+                        ),
+                )
+                .args(&delegation_terms_args())
+                .arg(
+                    Arg::with_name("duration")
+                        .long("duration")
+                        .value_name("SECONDS")
+                        .takes_value(true)
+                        .default_value("3600")
+                        .help(
+                            "Specify how long the session lasts, unless --valid-until is passed.",
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(CommandName::CloseSession.into())
+                .about("Close an expired session Delegation, returning the rent to its master")
+                .arg(
+                    Arg::with_name("delegation")
+                        .value_name("DELEGATION")
+                        .validator(is_valid_pubkey)
+                        .takes_value(true)
+                        .index(1)
+                        .help(
+                            "Specify the session delegation to close. \
+                            This must be a valid public key.",
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(CommandName::Request.into())
+                .about("Request Delegation from a master")
+                .arg(
+                    Arg::with_name("master")
+                        .value_name("MASTER")
+                        .validator(is_valid_pubkey)
+                        .takes_value(true)
+                        .index(1)
+                        .help(
+                            "Specify the master to request the delegation from. \
+                            This must be a valid public key.",
+                        ),
+                )
+                .args(&delegation_terms_args()),
+        )
+        .subcommand(
+            SubCommand::with_name(CommandName::Approve.into())
+                .about("Approve Delegation request")
+                .arg(
+                    Arg::with_name("delegation")
+                        .value_name("DELEGATION")
+                        .validator(is_valid_pubkey)
+                        .takes_value(true)
+                        .index(1)
+                        .help(
+                            "Specify the requested delegation to approve. \
+                            This must be a valid public key.",
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(CommandName::UpdateMetadata.into())
+                .about("Update the label and metadata URI of a Delegation")
+                .arg(
+                    Arg::with_name("delegation")
+                        .value_name("DELEGATION")
+                        .validator(is_valid_pubkey)
+                        .takes_value(true)
+                        .index(1)
+                        .help(
+                            "Specify the delegation to update. \
+                            This must be a valid public key.",
+                        ),
+                )
+                .args(&delegation_metadata_args()),
+        )
+        .subcommand(
+            SubCommand::with_name(CommandName::CreateRole.into())
+                .about("Create a Role defining permissions shared by the Delegations holding it")
+                .arg(
+                    Arg::with_name("name")
+                        .value_name("NAME")
+                        .takes_value(true)
+                        .index(1)
+                        .help("Specify the name of the role, e.g. operator."),
+                )
+                .args(&role_terms_args()),
+        )
+        .subcommand(
+            SubCommand::with_name(CommandName::UpdateRole.into())
+                .about("Update the permissions of a Role for every Delegation holding it")
+                .arg(
+                    Arg::with_name("role")
+                        .value_name("ROLE")
+                        .validator(is_valid_pubkey)
+                        .takes_value(true)
+                        .index(1)
+                        .help(
+                            "Specify the role to update. \
+                            This must be a valid public key.",
+                        ),
+                )
+                .args(&role_terms_args()),
+        )
+        .subcommand(
+            SubCommand::with_name(CommandName::CloseRole.into())
+                .about("Close a Role, returning the rent to its authority")
+                .arg(
+                    Arg::with_name("role")
+                        .value_name("ROLE")
+                        .validator(is_valid_pubkey)
+                        .takes_value(true)
+                        .index(1)
+                        .help(
+                            "Specify the role to close. \
+                            This must be a valid public key.",
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(CommandName::AssignRole.into())
+                .about("Let the permissions of a Role apply to a Delegation")
+                .arg(
+                    Arg::with_name("delegation")
+                        .value_name("DELEGATION")
+                        .validator(is_valid_pubkey)
+                        .takes_value(true)
+                        .index(1)
+                        .help(
+                            "Specify the delegation to assign the role to. \
+                            This must be a valid public key.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("role")
+                        .value_name("ROLE")
+                        .validator(is_valid_pubkey)
+                        .takes_value(true)
+                        .index(2)
+                        .help(
+                            "Specify the role to assign. \
+                            This must be a valid public key.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("protocol")
+                        .long("protocol")
+                        .takes_value(false)
+                        .help(
+                            "Assign a role of another authority, e.g. a protocol, \
+                            which may widen the delegation at any time.",
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(CommandName::UnassignRole.into())
+                .about("Let the own permissions of a Delegation apply again")
+                .arg(
+                    Arg::with_name("delegation")
+                        .value_name("DELEGATION")
+                        .validator(is_valid_pubkey)
+                        .takes_value(true)
+                        .index(1)
+                        .help(
+                            "Specify the delegation to unassign the role from. \
+                            This must be a valid public key.",
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(CommandName::PauseAll.into())
+                .about("Suspend all Delegations of the master"),
+        )
+        .subcommand(
+            SubCommand::with_name(CommandName::ResumeAll.into())
+                .about("Resume all suspended Delegations of the master"),
+        )
+        .subcommand(
+            SubCommand::with_name(CommandName::RevokeAll.into())
+                .about("Revoke all existing Delegations of the master"),
+        )
+        .subcommand(
+            SubCommand::with_name(CommandName::ProposeMasterTransfer.into())
+                .about("Propose a new wallet to transfer the Delegations of the master to")
+                .arg(
+                    Arg::with_name("new_master")
+                        .value_name("NEW_MASTER")
+                        .validator(is_valid_pubkey)
+                        .takes_value(true)
+                        .index(1)
+                        .help(
+                            "Specify the wallet to transfer the delegations to. \
+                            This must be a valid public key.",
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(CommandName::AcceptMasterTransfer.into())
+                .about("Accept the transfer of the Delegations of a master")
+                .arg(
+                    Arg::with_name("master")
+                        .value_name("MASTER")
+                        .validator(is_valid_pubkey)
+                        .takes_value(true)
+                        .index(1)
+                        .help(
+                            "Specify the master transferring its delegations. \
+                            This must be a valid public key.",
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(CommandName::CloseMasterTransfer.into())
+                .about("Withdraw or close the master transfer proposal"),
+        )
         .subcommand(
             SubCommand::with_name(CommandName::GetDelegations.into())
                 .about("Get Delegations")
@@ -139,46 +562,228 @@ pub fn app<'a, 'b>() -> App<'a, 'b> {
                         ),
                 ),
         )
-}
-
+        .subcommand(
+            SubCommand::with_name(CommandName::GetEvents.into())
+                .about("Get the lifecycle events of a Delegation")
+                .arg(
+                    Arg::with_name("delegation")
+                        .value_name("DELEGATION")
+                        .validator(is_valid_pubkey)
+                        .takes_value(true)
+                        .index(1)
+                        .help(
+                            "Displays the events emitted for a given delegation. \
+                            This must be a valid public key.",
+                        ),
+                ),
+        )
+}
+
+/// Arguments describing the terms of a delegation, shared by the commands creating one
+fn delegation_terms_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("permissions")
+            .short("p")
+            .long("permissions")
+            .value_name("BITMASK")
+            .takes_value(true)
+            .help(
+                "Specify the permissions bitmask granted to the representative. \
+                    Defaults to all permissions.",
+            ),
+        Arg::with_name("valid_from")
+            .long("valid-from")
+            .value_name("UNIX_TIMESTAMP")
+            .takes_value(true)
+            .help("Specify the unix timestamp from which the delegation is valid."),
+        Arg::with_name("valid_until")
+            .long("valid-until")
+            .value_name("UNIX_TIMESTAMP")
+            .takes_value(true)
+            .help("Specify the unix timestamp until which the delegation is valid."),
+        Arg::with_name("allowed_program")
+            .long("allowed-program")
+            .value_name("PROGRAM_ID")
+            .validator(is_valid_pubkey)
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .help(
+                "Restrict the delegation to the given program. \
+                    Can be repeated to allow multiple programs.",
+            ),
+        Arg::with_name("max_uses")
+            .long("max-uses")
+            .value_name("NUMBER")
+            .takes_value(true)
+            .help("Specify how many times the delegation may be used."),
+    ]
+}
+
+/// Parses the terms of a delegation from the arguments returned by delegation_terms_args
+fn delegation_terms_of(arg_matches: &ArgMatches<'_>) -> DelegationTerms {
+    DelegationTerms {
+        permissions: value_of::<u64>(arg_matches, "permissions").unwrap_or(PERMISSION_ALL),
+        valid_from: value_of::<i64>(arg_matches, "valid_from"),
+        valid_until: value_of::<i64>(arg_matches, "valid_until"),
+        allowed_programs: values_of::<Pubkey>(arg_matches, "allowed_program").unwrap_or_default(),
+        max_uses: value_of::<u32>(arg_matches, "max_uses"),
+    }
+}
+
+/// Arguments describing the terms of a role, shared by the commands setting them
+fn role_terms_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("permissions")
+            .short("p")
+            .long("permissions")
+            .value_name("BITMASK")
+            .takes_value(true)
+            .help("Specify the permissions bitmask granted to the holders of the role."),
+        Arg::with_name("allowed_program")
+            .long("allowed-program")
+            .value_name("PROGRAM_ID")
+            .validator(is_valid_pubkey)
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .help(
+                "Restrict the role to the given program. \
+                    Can be repeated to allow multiple programs.",
+            ),
+    ]
+}
+
+/// Arguments describing a delegation to its master, shared by the commands setting them
+fn delegation_metadata_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("label")
+            .long("label")
+            .value_name("LABEL")
+            .takes_value(true)
+            .help("Specify a label telling the delegation apart, e.g. the name of the bot."),
+        Arg::with_name("metadata_uri")
+            .long("metadata-uri")
+            .value_name("URI")
+            .takes_value(true)
+            .help("Specify the URI of off-chain metadata describing the delegation."),
+    ]
+}
+
+/// Parses the metadata of a delegation from the arguments returned by delegation_metadata_args,
+/// keeping the current values of the ones not passed
+fn delegation_metadata_of(
+    arg_matches: &ArgMatches<'_>,
+    current: DelegationMetadata,
+) -> DelegationMetadata {
+    DelegationMetadata {
+        label: value_of::<String>(arg_matches, "label").unwrap_or(current.label),
+        metadata_uri: value_of::<String>(arg_matches, "metadata_uri")
+            .unwrap_or(current.metadata_uri),
+    }
+}
+
 async fn command_initialize_delegate(
     config: &Config,
     signer: Arc<dyn Signer>,
+    payer: Arc<dyn Signer>,
     representative: Pubkey,
+    namespace: Option<Pubkey>,
+    terms: DelegationTerms,
+    metadata: DelegationMetadata,
 ) -> Result<(), Error> {
+    let (delegation, mut data) = if let Some(namespace) = namespace {
+        let mut data = sighash("global", "initialize_namespaced_delegate")
+            .try_to_vec()
+            .unwrap();
+        data.extend(namespace.try_to_vec().unwrap());
+        (
+            get_namespaced_delegation_address(&signer.pubkey(), &representative, &namespace),
+            data,
+        )
+    } else {
+        (
+            get_delegation_address(&signer.pubkey(), &representative),
+            sighash("global", "initialize_delegate")
+                .try_to_vec()
+                .unwrap(),
+        )
+    };
+    data.extend(terms.try_to_vec().unwrap());
+    data.extend(metadata.try_to_vec().unwrap());
+
     let instruction = Instruction {
         accounts: vec![
-            AccountMeta::new(signer.pubkey(), true),
+            AccountMeta::new_readonly(signer.pubkey(), true),
+            AccountMeta::new(payer.pubkey(), true),
             AccountMeta::new_readonly(representative, false),
-            AccountMeta::new(
-                get_delegation_address(&signer.pubkey(), &representative),
-                false,
-            ),
+            AccountMeta::new(delegation, false),
+            AccountMeta::new(get_master_index_address(&signer.pubkey()), false),
+            AccountMeta::new(get_representative_index_address(&representative), false),
+            AccountMeta::new_readonly(get_master_settings_address(&signer.pubkey()), false),
             AccountMeta::new(system_program::ID, false),
         ],
         program_id: config.program_id.clone(),
-        data: sighash("global", "initialize_delegate")
-            .try_to_vec()
-            .unwrap(),
+        data,
     };
 
-    let message = Message::new_with_blockhash(
-        &[instruction],
-        Some(&signer.pubkey()),
-        &config.rpc_client.get_latest_blockhash().await.unwrap(),
-    );
-    let signature = signer.sign_message(&message.serialize());
-    let mut transaction = Transaction::new_unsigned(message);
-    transaction
-        .replace_signatures(&[(signer.pubkey(), signature)])
-        .unwrap();
+    send_instructions_signed_by(config, &[payer, signer], &[instruction]).await
+}
 
-    config
-        .rpc_client
-        .send_and_confirm_transaction(&transaction)
-        .await
+/// Initializes a Delegation to each of the representatives, sending as many transactions as needed
+async fn command_initialize_delegates_batch(
+    config: &Config,
+    signer: Arc<dyn Signer>,
+    payer: Arc<dyn Signer>,
+    representatives: &[Pubkey],
+    terms: DelegationTerms,
+    metadata: DelegationMetadata,
+) -> Result<(), Error> {
+    let mut data = sighash("global", "initialize_delegates_batch")
+        .try_to_vec()
         .unwrap();
+    data.extend(terms.try_to_vec().unwrap());
+    data.extend(metadata.try_to_vec().unwrap());
+
+    let fixed_accounts = vec![
+        AccountMeta::new_readonly(signer.pubkey(), true),
+        AccountMeta::new(payer.pubkey(), true),
+        AccountMeta::new(get_master_index_address(&signer.pubkey()), false),
+        AccountMeta::new_readonly(get_master_settings_address(&signer.pubkey()), false),
+        AccountMeta::new(system_program::ID, false),
+    ];
+    let instruction_of = |accounts: Vec<AccountMeta>| Instruction {
+        accounts,
+        program_id: config.program_id.clone(),
+        data: data.clone(),
+    };
+    let signers = [payer.clone(), signer.clone()];
 
+    // as many representatives as fit the transaction, whose size depends on the terms
+    let mut accounts = fixed_accounts.clone();
+    for representative in representatives {
+        let representative_accounts = [
+            AccountMeta::new_readonly(*representative, false),
+            AccountMeta::new(
+                get_delegation_address(&signer.pubkey(), representative),
+                false,
+            ),
+            AccountMeta::new(get_representative_index_address(representative), false),
+        ];
+        if accounts.len() > fixed_accounts.len()
+            && !fits_in_transaction(
+                &instruction_of([accounts.as_slice(), &representative_accounts[..]].concat()),
+                &payer.pubkey(),
+            )
+        {
+            send_instructions_signed_by(config, &signers, &[instruction_of(accounts)]).await?;
+            accounts = fixed_accounts.clone();
+        }
+        accounts.extend(representative_accounts);
+    }
+    if accounts.len() > fixed_accounts.len() {
+        send_instructions_signed_by(config, &signers, &[instruction_of(accounts)]).await?;
+    }
     Ok(())
 }
 
@@ -191,51 +796,160 @@ async fn command_confirm_delegate(
         accounts: vec![
             AccountMeta::new(signer.pubkey(), true),
             AccountMeta::new(delegation, false),
+            AccountMeta::new(get_representative_index_address(&signer.pubkey()), false),
             AccountMeta::new(system_program::ID, false),
         ],
         program_id: config.program_id.clone(),
         data: sighash("global", "confirm_delegate").try_to_vec().unwrap(),
     };
 
-    let message = Message::new_with_blockhash(
-        &[instruction],
-        Some(&signer.pubkey()),
-        &config.rpc_client.get_latest_blockhash().await.unwrap(),
-    );
-    let signature = signer.sign_message(&message.serialize());
-    let mut transaction = Transaction::new_unsigned(message);
-    transaction
-        .replace_signatures(&[(signer.pubkey(), signature)])
-        .unwrap();
+    send_instructions(config, signer, &[instruction]).await
+}
 
-    config
-        .rpc_client
-        .send_and_confirm_transaction(&transaction)
-        .await
-        .unwrap();
+async fn command_sign_confirmation(
+    config: &Config,
+    signer: Arc<dyn Signer>,
+    delegation: Pubkey,
+) -> Result<(), Error> {
+    let account = fetch_delegation(config, &delegation).await?;
+    if account.representative != signer.pubkey() {
+        return Err(format!(
+            "{} is not the representative of {}",
+            signer.pubkey(),
+            delegation
+        )
+        .into());
+    }
+    let message = get_confirmation_message(
+        &delegation,
+        &account.master,
+        &account.representative,
+        account.created_slot,
+    );
 
+    println!("{}", signer.try_sign_message(&message)?);
     Ok(())
 }
 
+async fn command_confirm_delegate_with_signature(
+    config: &Config,
+    payer: Arc<dyn Signer>,
+    delegation: Pubkey,
+    signature: Signature,
+) -> Result<(), Error> {
+    let account = fetch_delegation(config, &delegation).await?;
+    let message = get_confirmation_message(
+        &delegation,
+        &account.master,
+        &account.representative,
+        account.created_slot,
+    );
+
+    let instruction = Instruction {
+        accounts: vec![
+            AccountMeta::new_readonly(account.representative, false),
+            AccountMeta::new(delegation, false),
+            AccountMeta::new(
+                get_representative_index_address(&account.representative),
+                false,
+            ),
+            AccountMeta::new_readonly(sysvar::instructions::ID, false),
+        ],
+        program_id: config.program_id.clone(),
+        data: sighash("global", "confirm_delegate_with_signature")
+            .try_to_vec()
+            .unwrap(),
+    };
+
+    send_instructions(
+        config,
+        payer,
+        &[
+            new_ed25519_instruction(&account.representative, &signature, &message),
+            instruction,
+        ],
+    )
+    .await
+}
+
+/// Builds an Ed25519 program instruction verifying the signature of the public key over the
+/// message, all three held in the instruction data itself
+fn new_ed25519_instruction(
+    public_key: &Pubkey,
+    signature: &Signature,
+    message: &[u8],
+) -> Instruction {
+    // one signature count and padding byte, followed by a single set of 7 u16 offsets
+    let public_key_offset: u16 = 16;
+    let signature_offset = public_key_offset + 32;
+    let message_offset = signature_offset + 64;
+    let mut data = vec![1, 0];
+    for offset in [
+        signature_offset,
+        u16::MAX,
+        public_key_offset,
+        u16::MAX,
+        message_offset,
+        message.len() as u16,
+        u16::MAX,
+    ] {
+        data.extend_from_slice(&offset.to_le_bytes());
+    }
+    data.extend_from_slice(public_key.as_ref());
+    data.extend_from_slice(signature.as_ref());
+    data.extend_from_slice(message);
+
+    Instruction {
+        program_id: ed25519_program::ID,
+        accounts: vec![],
+        data,
+    }
+}
+
+async fn command_decline_delegate(
+    config: &Config,
+    signer: Arc<dyn Signer>,
+    delegation: Pubkey,
+) -> Result<(), Error> {
+    let delegation_address = delegation;
+    let delegation = fetch_delegation(config, &delegation_address).await?;
+    let master = delegation.master;
+
+    let instruction = Instruction {
+        accounts: vec![
+            AccountMeta::new_readonly(signer.pubkey(), true),
+            AccountMeta::new_readonly(master, false),
+            AccountMeta::new(delegation_address, false),
+            AccountMeta::new(delegation.refund_recipient(), false),
+            AccountMeta::new(get_master_index_address(&master), false),
+            AccountMeta::new(get_representative_index_address(&signer.pubkey()), false),
+        ],
+        program_id: config.program_id.clone(),
+        data: sighash("global", "decline_delegate").try_to_vec().unwrap(),
+    };
+
+    send_instructions(config, signer, &[instruction]).await
+}
+
 async fn command_cancel_delegate(
     config: &Config,
     signer: Arc<dyn Signer>,
     delegation: Pubkey,
 ) -> Result<(), Error> {
+    let delegation_address = delegation;
+    let delegation = fetch_delegation(config, &delegation_address).await?;
+
     let mut accounts = vec![
-        AccountMeta::new(delegation, false),
+        AccountMeta::new(delegation_address, false),
+        AccountMeta::new(delegation.refund_recipient(), false),
+        AccountMeta::new(get_master_index_address(&delegation.master), false),
+        AccountMeta::new(
+            get_representative_index_address(&delegation.representative),
+            false,
+        ),
         AccountMeta::new_readonly(system_program::ID, false),
     ];
 
-    let delegation = config
-        .rpc_client
-        .get_program_accounts(&upl_delegation_manager::ID)
-        .await?
-        .iter()
-        .find(|(pubkey, _)| pubkey == &delegation)
-        .map(|(_, account)| try_from_slice_unchecked::<Delegation>(&account.data[8..]).unwrap())
-        .expect("Delegation account not found");
-
     if signer.pubkey() == delegation.master {
         accounts.push(AccountMeta::new(signer.pubkey(), true));
         accounts.push(AccountMeta::new(delegation.representative, false));
@@ -247,27 +961,510 @@ async fn command_cancel_delegate(
     let instruction = Instruction {
         accounts,
         program_id: config.program_id.clone(),
-        data: sighash("global", "cancel_delegate").try_to_vec().unwrap(),
+        data: sighash("global", "cancel_delegate").try_to_vec().unwrap(),
+    };
+
+    send_instructions(config, signer, &[instruction]).await
+}
+
+async fn command_rotate_representative(
+    config: &Config,
+    signer: Arc<dyn Signer>,
+    new_representative: Arc<dyn Signer>,
+    delegation: Pubkey,
+) -> Result<(), Error> {
+    let delegation_address = delegation;
+    let delegation = fetch_delegation(config, &delegation_address).await?;
+
+    let new_delegation = match (delegation.parent, delegation.namespace) {
+        (Some(parent), _) => get_sub_delegation_address(&parent, &new_representative.pubkey()),
+        (None, Some(namespace)) => get_namespaced_delegation_address(
+            &delegation.seed_key(),
+            &new_representative.pubkey(),
+            &namespace,
+        ),
+        (None, None) => {
+            get_delegation_address(&delegation.seed_key(), &new_representative.pubkey())
+        }
+    };
+
+    let instruction = Instruction {
+        accounts: vec![
+            AccountMeta::new(signer.pubkey(), true),
+            AccountMeta::new_readonly(new_representative.pubkey(), true),
+            AccountMeta::new_readonly(delegation.master, false),
+            AccountMeta::new(delegation_address, false),
+            AccountMeta::new(new_delegation, false),
+            AccountMeta::new(get_master_index_address(&delegation.master), false),
+            AccountMeta::new(get_representative_index_address(&signer.pubkey()), false),
+            AccountMeta::new(
+                get_representative_index_address(&new_representative.pubkey()),
+                false,
+            ),
+            AccountMeta::new_readonly(system_program::ID, false),
+        ],
+        program_id: config.program_id.clone(),
+        data: sighash("global", "rotate_representative")
+            .try_to_vec()
+            .unwrap(),
+    };
+
+    send_instructions_signed_by(config, &[signer, new_representative], &[instruction]).await
+}
+
+async fn command_set_rotation_allowed(
+    config: &Config,
+    signer: Arc<dyn Signer>,
+    delegation: Pubkey,
+    allowed: bool,
+) -> Result<(), Error> {
+    let mut data = sighash("global", "set_rotation_allowed")
+        .try_to_vec()
+        .unwrap();
+    data.extend(allowed.try_to_vec().unwrap());
+
+    let instruction = Instruction {
+        accounts: vec![
+            AccountMeta::new_readonly(signer.pubkey(), true),
+            AccountMeta::new(delegation, false),
+        ],
+        program_id: config.program_id.clone(),
+        data,
+    };
+
+    send_instructions(config, signer, &[instruction]).await
+}
+
+async fn command_migrate_delegation(
+    config: &Config,
+    signer: Arc<dyn Signer>,
+    delegation: Pubkey,
+) -> Result<(), Error> {
+    let legacy_delegation = fetch_delegation(config, &delegation).await?;
+
+    let instruction = Instruction {
+        accounts: vec![
+            AccountMeta::new(signer.pubkey(), true),
+            AccountMeta::new(delegation, false),
+            AccountMeta::new(get_master_index_address(&legacy_delegation.master), false),
+            AccountMeta::new(
+                get_representative_index_address(&legacy_delegation.representative),
+                false,
+            ),
+            AccountMeta::new_readonly(system_program::ID, false),
+        ],
+        program_id: config.program_id.clone(),
+        data: sighash("global", "migrate_delegation")
+            .try_to_vec()
+            .unwrap(),
+    };
+
+    send_instructions(config, signer, &[instruction]).await
+}
+
+async fn command_initialize_session_delegate(
+    config: &Config,
+    signer: Arc<dyn Signer>,
+    session_key: Pubkey,
+    terms: DelegationTerms,
+) -> Result<(), Error> {
+    let mut data = sighash("global", "initialize_session_delegate")
+        .try_to_vec()
+        .unwrap();
+    data.extend(terms.try_to_vec().unwrap());
+
+    let instruction = Instruction {
+        accounts: vec![
+            AccountMeta::new(signer.pubkey(), true),
+            AccountMeta::new_readonly(session_key, false),
+            AccountMeta::new(
+                get_delegation_address(&signer.pubkey(), &session_key),
+                false,
+            ),
+            AccountMeta::new(get_master_index_address(&signer.pubkey()), false),
+            AccountMeta::new_readonly(get_master_settings_address(&signer.pubkey()), false),
+            AccountMeta::new_readonly(system_program::ID, false),
+        ],
+        program_id: config.program_id.clone(),
+        data,
+    };
+
+    send_instructions(config, signer, &[instruction]).await
+}
+
+async fn command_close_expired_session(
+    config: &Config,
+    payer: Arc<dyn Signer>,
+    delegation: Pubkey,
+) -> Result<(), Error> {
+    let parsed_delegation = fetch_delegation(config, &delegation).await?;
+    let master = parsed_delegation.master;
+
+    let instruction = Instruction {
+        accounts: vec![
+            AccountMeta::new_readonly(master, false),
+            AccountMeta::new(delegation, false),
+            AccountMeta::new(parsed_delegation.refund_recipient(), false),
+            AccountMeta::new(get_master_index_address(&master), false),
+        ],
+        program_id: config.program_id.clone(),
+        data: sighash("global", "close_expired_session")
+            .try_to_vec()
+            .unwrap(),
+    };
+
+    send_instructions(config, payer, &[instruction]).await
+}
+
+async fn command_request_delegation(
+    config: &Config,
+    signer: Arc<dyn Signer>,
+    master: Pubkey,
+    terms: DelegationTerms,
+) -> Result<(), Error> {
+    let mut data = sighash("global", "request_delegation")
+        .try_to_vec()
+        .unwrap();
+    data.extend(terms.try_to_vec().unwrap());
+
+    let instruction = Instruction {
+        accounts: vec![
+            AccountMeta::new(signer.pubkey(), true),
+            AccountMeta::new_readonly(master, false),
+            AccountMeta::new(get_delegation_address(&master, &signer.pubkey()), false),
+            AccountMeta::new(get_representative_index_address(&signer.pubkey()), false),
+            AccountMeta::new(system_program::ID, false),
+        ],
+        program_id: config.program_id.clone(),
+        data,
+    };
+
+    send_instructions(config, signer, &[instruction]).await
+}
+
+async fn command_update_master_settings(
+    config: &Config,
+    signer: Arc<dyn Signer>,
+    instruction_name: &str,
+) -> Result<(), Error> {
+    let instruction = Instruction {
+        accounts: vec![
+            AccountMeta::new(signer.pubkey(), true),
+            AccountMeta::new(get_master_settings_address(&signer.pubkey()), false),
+            AccountMeta::new_readonly(system_program::ID, false),
+        ],
+        program_id: config.program_id.clone(),
+        data: sighash("global", instruction_name).try_to_vec().unwrap(),
+    };
+
+    send_instructions(config, signer, &[instruction]).await
+}
+
+async fn command_propose_master_transfer(
+    config: &Config,
+    signer: Arc<dyn Signer>,
+    new_master: Pubkey,
+) -> Result<(), Error> {
+    let mut data = sighash("global", "propose_master_transfer")
+        .try_to_vec()
+        .unwrap();
+    data.extend(new_master.try_to_vec().unwrap());
+
+    let instruction = Instruction {
+        accounts: vec![
+            AccountMeta::new(signer.pubkey(), true),
+            AccountMeta::new(get_master_transfer_address(&signer.pubkey()), false),
+            AccountMeta::new_readonly(system_program::ID, false),
+        ],
+        program_id: config.program_id.clone(),
+        data,
+    };
+
+    send_instructions(config, signer, &[instruction]).await
+}
+
+/// Cancels every Delegation listed in the index of the signer as master or as representative,
+/// packing as many of them in each transaction as fit
+async fn command_cancel_all_delegates(
+    config: &Config,
+    signer: Arc<dyn Signer>,
+    as_master: bool,
+) -> Result<(), Error> {
+    let delegation_type = if as_master { "master" } else { "repr" };
+    let addresses = fetch_delegation_addresses(config, &signer.pubkey(), delegation_type).await?;
+
+    let mut delegations = vec![];
+    for chunk in addresses.chunks(MAX_MULTIPLE_ACCOUNTS) {
+        let accounts = config.rpc_client.get_multiple_accounts(chunk).await?;
+        for (address, account) in chunk.iter().zip(accounts) {
+            if let Some(account) = account {
+                let account = Delegation::try_deserialize_versioned(&account.data)?;
+                let party = if as_master {
+                    account.master
+                } else {
+                    account.representative
+                };
+                if party != signer.pubkey() {
+                    continue;
+                }
+                delegations.push((*address, account));
+            }
+        }
+    }
+
+    let fixed_accounts = vec![
+        AccountMeta::new_readonly(signer.pubkey(), true),
+        AccountMeta::new(get_master_index_address(&signer.pubkey()), false),
+        AccountMeta::new(get_representative_index_address(&signer.pubkey()), false),
+    ];
+    let instruction_of = |accounts: Vec<AccountMeta>| Instruction {
+        accounts,
+        program_id: config.program_id.clone(),
+        data: sighash("global", "cancel_delegates_batch")
+            .try_to_vec()
+            .unwrap(),
+    };
+
+    let mut accounts = fixed_accounts.clone();
+    for (address, delegation) in delegations {
+        let index = if as_master {
+            get_representative_index_address(&delegation.representative)
+        } else {
+            get_master_index_address(&delegation.master)
+        };
+        let delegation_accounts = [
+            AccountMeta::new(address, false),
+            AccountMeta::new(delegation.refund_recipient(), false),
+            AccountMeta::new(index, false),
+        ];
+        if accounts.len() > fixed_accounts.len()
+            && !fits_in_transaction(
+                &instruction_of([accounts.as_slice(), &delegation_accounts[..]].concat()),
+                &signer.pubkey(),
+            )
+        {
+            send_instructions(config, signer.clone(), &[instruction_of(accounts)]).await?;
+            accounts = fixed_accounts.clone();
+        }
+        accounts.extend(delegation_accounts);
+    }
+    if accounts.len() > fixed_accounts.len() {
+        send_instructions(config, signer, &[instruction_of(accounts)]).await?;
+    }
+
+    Ok(())
+}
+
+async fn command_accept_master_transfer(
+    config: &Config,
+    signer: Arc<dyn Signer>,
+    master: Pubkey,
+) -> Result<(), Error> {
+    // sub-delegations stay with the wallet they were re-delegated by
+    let mut delegations = vec![];
+    for delegation in fetch_master_index(config, &master).await? {
+        let account = fetch_delegation(config, &delegation).await?;
+        if account.master == master && account.parent.is_none() {
+            delegations.push((delegation, account.representative));
+        }
+    }
+
+    for chunk in delegations.chunks(MAX_TRANSFERS_PER_TRANSACTION) {
+        let mut accounts = vec![
+            AccountMeta::new(signer.pubkey(), true),
+            AccountMeta::new_readonly(master, false),
+            AccountMeta::new_readonly(get_master_transfer_address(&master), false),
+            AccountMeta::new_readonly(get_master_settings_address(&master), false),
+            AccountMeta::new_readonly(get_master_settings_address(&signer.pubkey()), false),
+            AccountMeta::new(get_master_index_address(&master), false),
+            AccountMeta::new(get_master_index_address(&signer.pubkey()), false),
+            AccountMeta::new_readonly(system_program::ID, false),
+        ];
+        for (delegation, representative) in chunk {
+            accounts.push(AccountMeta::new(*delegation, false));
+            accounts.push(AccountMeta::new(
+                get_representative_index_address(representative),
+                false,
+            ));
+        }
+
+        let instruction = Instruction {
+            accounts,
+            program_id: config.program_id.clone(),
+            data: sighash("global", "accept_master_transfer")
+                .try_to_vec()
+                .unwrap(),
+        };
+
+        send_instructions(config, signer.clone(), &[instruction]).await?;
+    }
+
+    Ok(())
+}
+
+async fn command_close_master_transfer(
+    config: &Config,
+    signer: Arc<dyn Signer>,
+) -> Result<(), Error> {
+    let instruction = Instruction {
+        accounts: vec![
+            AccountMeta::new(signer.pubkey(), true),
+            AccountMeta::new(get_master_transfer_address(&signer.pubkey()), false),
+        ],
+        program_id: config.program_id.clone(),
+        data: sighash("global", "close_master_transfer")
+            .try_to_vec()
+            .unwrap(),
+    };
+
+    send_instructions(config, signer, &[instruction]).await
+}
+
+async fn command_update_delegation_metadata(
+    config: &Config,
+    signer: Arc<dyn Signer>,
+    delegation: Pubkey,
+    metadata: DelegationMetadata,
+) -> Result<(), Error> {
+    let mut data = sighash("global", "update_delegation_metadata")
+        .try_to_vec()
+        .unwrap();
+    data.extend(metadata.try_to_vec().unwrap());
+
+    let instruction = Instruction {
+        accounts: vec![
+            AccountMeta::new_readonly(signer.pubkey(), true),
+            AccountMeta::new(delegation, false),
+        ],
+        program_id: config.program_id.clone(),
+        data,
+    };
+
+    send_instructions(config, signer, &[instruction]).await
+}
+
+async fn command_create_role(
+    config: &Config,
+    signer: Arc<dyn Signer>,
+    name: String,
+    permissions: u64,
+    allowed_programs: Vec<Pubkey>,
+) -> Result<(), Error> {
+    let role = get_role_address(&signer.pubkey(), &name);
+    println!("Role address: {}", role);
+
+    let mut data = sighash("global", "create_role").try_to_vec().unwrap();
+    data.extend(name.try_to_vec().unwrap());
+    data.extend(permissions.try_to_vec().unwrap());
+    data.extend(allowed_programs.try_to_vec().unwrap());
+
+    let instruction = Instruction {
+        accounts: vec![
+            AccountMeta::new(signer.pubkey(), true),
+            AccountMeta::new(role, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        program_id: config.program_id.clone(),
+        data,
+    };
+
+    send_instructions(config, signer, &[instruction]).await
+}
+
+async fn command_update_role(
+    config: &Config,
+    signer: Arc<dyn Signer>,
+    role: Pubkey,
+    permissions: u64,
+    allowed_programs: Vec<Pubkey>,
+) -> Result<(), Error> {
+    let mut data = sighash("global", "update_role").try_to_vec().unwrap();
+    data.extend(permissions.try_to_vec().unwrap());
+    data.extend(allowed_programs.try_to_vec().unwrap());
+
+    let instruction = Instruction {
+        accounts: vec![
+            AccountMeta::new_readonly(signer.pubkey(), true),
+            AccountMeta::new(role, false),
+        ],
+        program_id: config.program_id.clone(),
+        data,
+    };
+
+    send_instructions(config, signer, &[instruction]).await
+}
+
+async fn command_close_role(
+    config: &Config,
+    signer: Arc<dyn Signer>,
+    role: Pubkey,
+) -> Result<(), Error> {
+    let instruction = Instruction {
+        accounts: vec![
+            AccountMeta::new(signer.pubkey(), true),
+            AccountMeta::new(role, false),
+        ],
+        program_id: config.program_id.clone(),
+        data: sighash("global", "close_role").try_to_vec().unwrap(),
+    };
+
+    send_instructions(config, signer, &[instruction]).await
+}
+
+/// Assigns the role to the delegation, along with whether it is a protocol role, or unassigns
+/// its current role if None
+async fn command_assign_role(
+    config: &Config,
+    signer: Arc<dyn Signer>,
+    delegation: Pubkey,
+    role: Option<(Pubkey, bool)>,
+) -> Result<(), Error> {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(signer.pubkey(), true),
+        AccountMeta::new(delegation, false),
+    ];
+    let data = match role {
+        Some((role, protocol_role)) => {
+            accounts.push(AccountMeta::new_readonly(role, false));
+            let mut data = sighash("global", "assign_role").try_to_vec().unwrap();
+            data.extend(protocol_role.try_to_vec().unwrap());
+            data
+        }
+        None => sighash("global", "unassign_role").try_to_vec().unwrap(),
+    };
+
+    let instruction = Instruction {
+        accounts,
+        program_id: config.program_id.clone(),
+        data,
     };
 
-    let message = Message::new_with_blockhash(
-        &[instruction],
-        Some(&signer.pubkey()),
-        &config.rpc_client.get_latest_blockhash().await.unwrap(),
-    );
-    let signature = signer.sign_message(&message.serialize());
-    let mut transaction = Transaction::new_unsigned(message);
-    transaction
-        .replace_signatures(&[(signer.pubkey(), signature)])
-        .unwrap();
+    send_instructions(config, signer, &[instruction]).await
+}
 
-    config
-        .rpc_client
-        .send_and_confirm_transaction(&transaction)
-        .await
-        .unwrap();
+async fn command_approve_delegation_request(
+    config: &Config,
+    signer: Arc<dyn Signer>,
+    delegation: Pubkey,
+) -> Result<(), Error> {
+    let representative = fetch_delegation(config, &delegation).await?.representative;
 
-    Ok(())
+    let instruction = Instruction {
+        accounts: vec![
+            AccountMeta::new(signer.pubkey(), true),
+            AccountMeta::new(delegation, false),
+            AccountMeta::new(get_master_index_address(&signer.pubkey()), false),
+            AccountMeta::new(get_representative_index_address(&representative), false),
+            AccountMeta::new_readonly(get_master_settings_address(&signer.pubkey()), false),
+            AccountMeta::new_readonly(system_program::ID, false),
+        ],
+        program_id: config.program_id.clone(),
+        data: sighash("global", "approve_delegation_request")
+            .try_to_vec()
+            .unwrap(),
+    };
+
+    send_instructions(config, signer, &[instruction]).await
 }
 
 async fn command_get_delegations(
@@ -276,21 +1473,12 @@ async fn command_get_delegations(
     delegation_type: &str,
     delegation: Option<Pubkey>,
 ) -> Result<(), Error> {
-    let delegation_accounts = config
-        .rpc_client
-        .get_program_accounts(&upl_delegation_manager::ID)
-        .await?;
-
     let mut table = Table::new();
-    table.set_titles(row![bic => cell!("Delegation"), cell!("Account")]);
+    table.set_titles(row![bic => cell!("Delegation"), cell!("Label"), cell!("Account")]);
 
     if let Some(delegation) = delegation {
         // delegation_type must be all
-        let parsed_delegation = delegation_accounts
-            .iter()
-            .find(|(pubkey, _)| pubkey == &delegation)
-            .map(|(_, account)| try_from_slice_unchecked::<Delegation>(&account.data[8..]).unwrap())
-            .expect("Pubkey provided does not match any delegation");
+        let parsed_delegation = fetch_delegation(config, &delegation).await?;
         try_add_row_for_delegation_type(
             &mut table,
             delegation_type,
@@ -299,19 +1487,22 @@ async fn command_get_delegations(
             pubkey,
         );
     } else {
-        delegation_accounts
-            .iter()
-            .filter(|(_, account)| account.data[0..8].feq(&Delegation::discriminator()))
-            .for_each(|(address, account)| {
-                let account = try_from_slice_unchecked::<Delegation>(&account.data[8..]).unwrap();
-                try_add_row_for_delegation_type(
-                    &mut table,
-                    delegation_type,
-                    address,
-                    &account,
-                    pubkey,
-                );
-            });
+        let addresses = fetch_delegation_addresses(config, pubkey, delegation_type).await?;
+        for chunk in addresses.chunks(MAX_MULTIPLE_ACCOUNTS) {
+            let accounts = config.rpc_client.get_multiple_accounts(chunk).await?;
+            for (address, account) in chunk.iter().zip(accounts) {
+                if let Some(account) = account {
+                    let account = Delegation::try_deserialize_versioned(&account.data)?;
+                    try_add_row_for_delegation_type(
+                        &mut table,
+                        delegation_type,
+                        address,
+                        &account,
+                        pubkey,
+                    );
+                }
+            }
+        }
     }
 
     table.printstd();
@@ -319,6 +1510,189 @@ async fn command_get_delegations(
     Ok(())
 }
 
+/// Returns the Delegations of the wallet as master, representative or both, depending on the
+/// delegation type. The indexes avoid scanning every account owned by the program, which is
+/// only searched for the delegations they don't list, and skipped with a warning on RPC
+/// providers disabling getProgramAccounts.
+async fn fetch_delegation_addresses(
+    config: &Config,
+    wallet: &Pubkey,
+    delegation_type: &str,
+) -> Result<Vec<Pubkey>, Error> {
+    let mut addresses = vec![];
+    let mut sides = vec![];
+    if delegation_type != "repr" {
+        sides.push((fetch_master_index(config, wallet).await?, 8));
+    }
+    if delegation_type != "master" {
+        sides.push((fetch_representative_index(config, wallet).await?, 40));
+    }
+    for (listed, field_offset) in sides {
+        let index_full = listed.len() >= MAX_INDEX_ENTRIES;
+        addresses.extend(listed);
+        match fetch_unlisted_delegations(config, wallet, field_offset, index_full).await {
+            Ok(unlisted) => addresses.extend(unlisted),
+            Err(error) => eprintln!(
+                "Warning: only listing indexed delegations, scanning the program accounts \
+                 failed: {}",
+                error
+            ),
+        }
+    }
+    addresses.sort();
+    addresses.dedup();
+    Ok(addresses)
+}
+
+/// Returns the Delegations listed in the index of the master, none if it doesn't exist
+async fn fetch_master_index(config: &Config, master: &Pubkey) -> Result<Vec<Pubkey>, Error> {
+    match config
+        .rpc_client
+        .get_account_with_commitment(
+            &get_master_index_address(master),
+            config.rpc_client.commitment(),
+        )
+        .await?
+        .value
+    {
+        Some(account) => Ok(MasterIndex::try_deserialize(&mut account.data.as_slice())?
+            .delegations
+            .iter()
+            .map(|entry| entry.delegation)
+            .collect()),
+        None => Ok(vec![]),
+    }
+}
+
+/// Returns the Delegations listed in the index of the representative, none if it doesn't exist
+async fn fetch_representative_index(
+    config: &Config,
+    representative: &Pubkey,
+) -> Result<Vec<Pubkey>, Error> {
+    match config
+        .rpc_client
+        .get_account_with_commitment(
+            &get_representative_index_address(representative),
+            config.rpc_client.commitment(),
+        )
+        .await?
+        .value
+    {
+        Some(account) => Ok(
+            RepresentativeIndex::try_deserialize(&mut account.data.as_slice())?
+                .delegations
+                .iter()
+                .map(|entry| entry.delegation)
+                .collect(),
+        ),
+        None => Ok(vec![]),
+    }
+}
+
+/// Returns the Delegations of the wallet its index doesn't list, by scanning the program
+/// accounts: legacy delegations, created before the indexes, and the ones created once the
+/// index is full. The field_offset is the offset of the master or representative field in the
+/// legacy layout, where the current one starts with the version.
+async fn fetch_unlisted_delegations(
+    config: &Config,
+    wallet: &Pubkey,
+    field_offset: usize,
+    index_full: bool,
+) -> Result<Vec<Pubkey>, Error> {
+    let mut layouts = vec![(Delegation::LEGACY_SPACE, field_offset)];
+    if index_full {
+        layouts.push((Delegation::SPACE, field_offset + 1));
+    }
+
+    let mut addresses = vec![];
+    for (space, offset) in layouts {
+        let accounts = config
+            .rpc_client
+            .get_program_accounts_with_config(
+                &config.program_id,
+                RpcProgramAccountsConfig {
+                    filters: Some(vec![
+                        RpcFilterType::DataSize(space as u64),
+                        RpcFilterType::Memcmp(Memcmp::new_base58_encoded(offset, wallet.as_ref())),
+                    ]),
+                    account_config: RpcAccountInfoConfig {
+                        encoding: Some(UiAccountEncoding::Base64),
+                        ..RpcAccountInfoConfig::default()
+                    },
+                    ..RpcProgramAccountsConfig::default()
+                },
+            )
+            .await?;
+        addresses.extend(
+            accounts
+                .iter()
+                .filter(|(_, account)| account.data[0..8] == Delegation::discriminator())
+                .map(|(address, _)| *address),
+        );
+    }
+    Ok(addresses)
+}
+
+async fn fetch_delegation(config: &Config, delegation: &Pubkey) -> Result<Delegation, Error> {
+    let data = config.rpc_client.get_account_data(delegation).await?;
+    Ok(Delegation::try_deserialize_versioned(&data)?)
+}
+
+async fn fetch_role(config: &Config, role: &Pubkey) -> Result<Role, Error> {
+    let data = config.rpc_client.get_account_data(role).await?;
+    Ok(Role::try_deserialize(&mut data.as_slice())?)
+}
+
+async fn send_instructions(
+    config: &Config,
+    signer: Arc<dyn Signer>,
+    instructions: &[Instruction],
+) -> Result<(), Error> {
+    send_instructions_signed_by(config, &[signer], instructions).await
+}
+
+/// Returns whether a transaction holding the instruction, signed by the required signers,
+/// fits the packet size limit
+fn fits_in_transaction(instruction: &Instruction, payer: &Pubkey) -> bool {
+    let message = Message::new(&[instruction.clone()], Some(payer));
+    1 + 64 * usize::from(message.header.num_required_signatures) + message.serialize().len()
+        <= PACKET_DATA_SIZE
+}
+
+/// Sends the instructions in a transaction paid by the first signer and signed by all of them.
+/// The same wallet may be passed more than once, e.g. when the payer is the default signer.
+async fn send_instructions_signed_by(
+    config: &Config,
+    signers: &[Arc<dyn Signer>],
+    instructions: &[Instruction],
+) -> Result<(), Error> {
+    let message = Message::new_with_blockhash(
+        instructions,
+        Some(&signers[0].pubkey()),
+        &config.rpc_client.get_latest_blockhash().await.unwrap(),
+    );
+    let message_data = message.serialize();
+    let mut signatures: Vec<(Pubkey, Signature)> = vec![];
+    for signer in signers {
+        if signatures
+            .iter()
+            .all(|(pubkey, _)| pubkey != &signer.pubkey())
+        {
+            signatures.push((signer.pubkey(), signer.sign_message(&message_data)));
+        }
+    }
+    let mut transaction = Transaction::new_unsigned(message);
+    transaction.replace_signatures(&signatures).unwrap();
+
+    config
+        .rpc_client
+        .send_and_confirm_transaction(&transaction)
+        .await
+        .unwrap();
+
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     let app_matches = app().get_matches();
@@ -342,14 +1716,42 @@ async fn process_command<'a>(
 ) -> Result<(), Error> {
     match (sub_command, sub_matches) {
         (CommandName::Initialize, arg_matches) => {
-            let recipient = pubkey_of_signer(arg_matches, "representative", &mut wallet_manager)
-                .unwrap()
-                .unwrap();
+            let representatives =
+                pubkeys_of_multiple_signers(arg_matches, "representative", &mut wallet_manager)
+                    .unwrap()
+                    .expect("You must provide at least one representative");
             let (owner_signer, _) =
                 config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
+            let namespace = value_of::<Pubkey>(arg_matches, "namespace");
+            let terms = delegation_terms_of(arg_matches);
+            let metadata = delegation_metadata_of(arg_matches, DelegationMetadata::default());
+            // the fee payer, which is the owner unless --fee-payer is passed, sponsors the rent
+            let payer = config.fee_payer()?;
 
-            command_initialize_delegate(config, owner_signer, recipient).await?;
-            Ok(())
+            if let [representative] = representatives[..] {
+                command_initialize_delegate(
+                    config,
+                    owner_signer,
+                    payer,
+                    representative,
+                    namespace,
+                    terms,
+                    metadata,
+                )
+                .await
+            } else if namespace.is_some() {
+                Err("namespaced delegations can only be initialized one at a time".into())
+            } else {
+                command_initialize_delegates_batch(
+                    config,
+                    owner_signer,
+                    payer,
+                    &representatives,
+                    terms,
+                    metadata,
+                )
+                .await
+            }
         }
         (CommandName::Confirm, arg_matches) => {
             let (owner_signer, _) =
@@ -359,6 +1761,36 @@ async fn process_command<'a>(
 
             command_confirm_delegate(config, owner_signer, delegation).await
         }
+        (CommandName::SignConfirmation, arg_matches) => {
+            let (owner_signer, _) =
+                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
+            let delegation = value_of::<Pubkey>(arg_matches, "delegation")
+                .expect("You must provide delegation address");
+
+            command_sign_confirmation(config, owner_signer, delegation).await
+        }
+        (CommandName::ConfirmWithSignature, arg_matches) => {
+            let delegation = value_of::<Pubkey>(arg_matches, "delegation")
+                .expect("You must provide delegation address");
+            let signature = value_of::<Signature>(arg_matches, "signature")
+                .expect("You must provide a valid representative signature");
+
+            command_confirm_delegate_with_signature(
+                config,
+                config.fee_payer()?,
+                delegation,
+                signature,
+            )
+            .await
+        }
+        (CommandName::Decline, arg_matches) => {
+            let (owner_signer, _) =
+                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
+            let delegation = value_of::<Pubkey>(arg_matches, "delegation")
+                .expect("You must provide delegation address");
+
+            command_decline_delegate(config, owner_signer, delegation).await
+        }
         (CommandName::Cancel, arg_matches) => {
             let (owner_signer, _) =
                 config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
@@ -367,6 +1799,195 @@ async fn process_command<'a>(
 
             command_cancel_delegate(config, owner_signer, delegation).await
         }
+        (CommandName::CancelAll, arg_matches) => {
+            let (owner_signer, _) =
+                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
+            let as_master = arg_matches.value_of("as") == Some("master");
+
+            command_cancel_all_delegates(config, owner_signer, as_master).await
+        }
+        (CommandName::Rotate, arg_matches) => {
+            let (owner_signer, _) =
+                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
+            let (new_representative, _) =
+                config.signer_or_default(arg_matches, "new_representative", &mut wallet_manager);
+            let delegation = value_of::<Pubkey>(arg_matches, "delegation")
+                .expect("You must provide delegation address");
+
+            command_rotate_representative(config, owner_signer, new_representative, delegation)
+                .await
+        }
+        (CommandName::SetRotationAllowed, arg_matches) => {
+            let (owner_signer, _) =
+                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
+            let delegation = value_of::<Pubkey>(arg_matches, "delegation")
+                .expect("You must provide delegation address");
+            let allowed =
+                value_of::<bool>(arg_matches, "allowed").expect("You must provide true or false");
+
+            command_set_rotation_allowed(config, owner_signer, delegation, allowed).await
+        }
+        (CommandName::Migrate, arg_matches) => {
+            let (owner_signer, _) =
+                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
+            let delegation = value_of::<Pubkey>(arg_matches, "delegation")
+                .expect("You must provide delegation address");
+
+            command_migrate_delegation(config, owner_signer, delegation).await
+        }
+        (CommandName::CreateSession, arg_matches) => {
+            let session_key = value_of::<Pubkey>(arg_matches, "session_key")
+                .expect("You must provide the session key");
+            let (owner_signer, _) =
+                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
+            let mut terms = delegation_terms_of(arg_matches);
+            if terms.valid_until.is_none() {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+                terms.valid_until = Some(now + value_of::<i64>(arg_matches, "duration").unwrap());
+            }
+
+            command_initialize_session_delegate(config, owner_signer, session_key, terms).await
+        }
+        (CommandName::CloseSession, arg_matches) => {
+            let delegation = value_of::<Pubkey>(arg_matches, "delegation")
+                .expect("You must provide delegation address");
+
+            command_close_expired_session(config, config.fee_payer()?, delegation).await
+        }
+        (CommandName::Request, arg_matches) => {
+            let master = pubkey_of_signer(arg_matches, "master", &mut wallet_manager)
+                .unwrap()
+                .expect("You must provide master address");
+            let (owner_signer, _) =
+                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
+            let terms = delegation_terms_of(arg_matches);
+
+            command_request_delegation(config, owner_signer, master, terms).await
+        }
+        (CommandName::UpdateMetadata, arg_matches) => {
+            let (owner_signer, _) =
+                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
+            let delegation = value_of::<Pubkey>(arg_matches, "delegation")
+                .expect("You must provide delegation address");
+            let current = fetch_delegation(config, &delegation).await?;
+            let metadata = delegation_metadata_of(
+                arg_matches,
+                DelegationMetadata {
+                    label: current.label,
+                    metadata_uri: current.metadata_uri,
+                },
+            );
+
+            command_update_delegation_metadata(config, owner_signer, delegation, metadata).await
+        }
+        (CommandName::CreateRole, arg_matches) => {
+            let (owner_signer, _) =
+                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
+            let name =
+                value_of::<String>(arg_matches, "name").expect("You must provide the role name");
+            let permissions = value_of::<u64>(arg_matches, "permissions").unwrap_or(PERMISSION_ALL);
+            let allowed_programs =
+                values_of::<Pubkey>(arg_matches, "allowed_program").unwrap_or_default();
+
+            command_create_role(config, owner_signer, name, permissions, allowed_programs).await
+        }
+        (CommandName::UpdateRole, arg_matches) => {
+            let (owner_signer, _) =
+                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
+            let role =
+                value_of::<Pubkey>(arg_matches, "role").expect("You must provide role address");
+            let current = fetch_role(config, &role).await?;
+            let permissions =
+                value_of::<u64>(arg_matches, "permissions").unwrap_or(current.permissions);
+            let allowed_programs = values_of::<Pubkey>(arg_matches, "allowed_program")
+                .unwrap_or(current.allowed_programs);
+
+            command_update_role(config, owner_signer, role, permissions, allowed_programs).await
+        }
+        (CommandName::CloseRole, arg_matches) => {
+            let (owner_signer, _) =
+                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
+            let role =
+                value_of::<Pubkey>(arg_matches, "role").expect("You must provide role address");
+
+            command_close_role(config, owner_signer, role).await
+        }
+        (CommandName::AssignRole, arg_matches) => {
+            let (owner_signer, _) =
+                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
+            let delegation = value_of::<Pubkey>(arg_matches, "delegation")
+                .expect("You must provide delegation address");
+            let role =
+                value_of::<Pubkey>(arg_matches, "role").expect("You must provide role address");
+
+            let protocol_role = arg_matches.is_present("protocol");
+
+            command_assign_role(
+                config,
+                owner_signer,
+                delegation,
+                Some((role, protocol_role)),
+            )
+            .await
+        }
+        (CommandName::UnassignRole, arg_matches) => {
+            let (owner_signer, _) =
+                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
+            let delegation = value_of::<Pubkey>(arg_matches, "delegation")
+                .expect("You must provide delegation address");
+
+            command_assign_role(config, owner_signer, delegation, None).await
+        }
+        (CommandName::PauseAll, arg_matches) => {
+            let (owner_signer, _) =
+                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
+
+            command_update_master_settings(config, owner_signer, "pause_all").await
+        }
+        (CommandName::ResumeAll, arg_matches) => {
+            let (owner_signer, _) =
+                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
+
+            command_update_master_settings(config, owner_signer, "resume_all").await
+        }
+        (CommandName::RevokeAll, arg_matches) => {
+            let (owner_signer, _) =
+                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
+
+            command_update_master_settings(config, owner_signer, "revoke_all").await
+        }
+        (CommandName::ProposeMasterTransfer, arg_matches) => {
+            let new_master = pubkey_of_signer(arg_matches, "new_master", &mut wallet_manager)
+                .unwrap()
+                .expect("You must provide new master address");
+            let (owner_signer, _) =
+                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
+
+            command_propose_master_transfer(config, owner_signer, new_master).await
+        }
+        (CommandName::AcceptMasterTransfer, arg_matches) => {
+            let master = pubkey_of_signer(arg_matches, "master", &mut wallet_manager)
+                .unwrap()
+                .expect("You must provide master address");
+            let (owner_signer, _) =
+                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
+
+            command_accept_master_transfer(config, owner_signer, master).await
+        }
+        (CommandName::CloseMasterTransfer, arg_matches) => {
+            let (owner_signer, _) =
+                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
+
+            command_close_master_transfer(config, owner_signer).await
+        }
+        (CommandName::Approve, arg_matches) => {
+            let (owner_signer, _) =
+                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
+            let delegation = value_of::<Pubkey>(arg_matches, "delegation")
+                .expect("You must provide delegation address");
+
+            command_approve_delegation_request(config, owner_signer, delegation).await
+        }
         (CommandName::GetDelegations, arg_matches) => {
             let pubkey = config.pubkey_or_default(arg_matches, "owner", &mut wallet_manager)?;
             let delegation = value_of::<Pubkey>(arg_matches, "delegation");
@@ -387,7 +2008,51 @@ async fn process_command<'a>(
             command_get_delegations(config, &pubkey, delegation_type.as_str(), delegation).await?;
             Ok(())
         }
+        (CommandName::GetEvents, arg_matches) => {
+            let delegation = value_of::<Pubkey>(arg_matches, "delegation")
+                .expect("You must provide delegation address");
+
+            command_get_events(config, delegation).await
+        }
+    }
+}
+
+async fn command_get_events(config: &Config, delegation: Pubkey) -> Result<(), Error> {
+    let mut table = Table::new();
+    table.set_titles(row![bic => cell!("Slot"), cell!("Event"), cell!("Details")]);
+
+    let signatures = config
+        .rpc_client
+        .get_signatures_for_address(&delegation)
+        .await?;
+
+    for status in signatures
+        .iter()
+        .rev()
+        .filter(|status| status.err.is_none())
+    {
+        let transaction = config
+            .rpc_client
+            .get_transaction(
+                &Signature::from_str(&status.signature)?,
+                UiTransactionEncoding::Json,
+            )
+            .await?;
+        let logs: Option<Vec<String>> = transaction
+            .transaction
+            .meta
+            .and_then(|meta| meta.log_messages.into());
+
+        DelegationEvent::parse_logs(&config.program_id, &logs.unwrap_or_default())
+            .iter()
+            .filter(|event| event.delegation() == delegation)
+            .for_each(|event| {
+                table.add_row(row![event.slot(), event.name(), event.details()]);
+            });
     }
+
+    table.printstd();
+    Ok(())
 }
 
 pub fn sighash(namespace: &str, name: &str) -> [u8; 8] {
@@ -405,40 +2070,72 @@ pub fn try_add_row_for_delegation_type(
     account: &Delegation,
     pubkey: &Pubkey,
 ) {
-    match delegation_type {
-        "all" => {
-            if &account.master == pubkey || &account.representative == pubkey {
-                table.add_row(row![
-                    address,
-                    format!(
-                        "master: {}\nrepresentative: {}\nauthorised: {}",
-                        account.master, account.representative, account.authorised
-                    )
-                ]);
-            }
-        }
-        "master" => {
-            if &account.master == pubkey {
-                table.add_row(row![
-                    address,
-                    format!(
-                        "master: {}\nrepresentative: {}\nauthorised: {}",
-                        account.master, account.representative, account.authorised
-                    )
-                ]);
-            }
-        }
-        "repr" => {
-            if &account.representative == pubkey {
-                table.add_row(row![
-                    address,
-                    format!(
-                        "master: {}\nrepresentative: {}\nauthorised: {}",
-                        account.master, account.representative, account.authorised
-                    )
-                ]);
-            }
-        }
+    let matches = match delegation_type {
+        "all" => &account.master == pubkey || &account.representative == pubkey,
+        "master" => &account.master == pubkey,
+        "repr" => &account.representative == pubkey,
         _ => todo!(),
+    };
+    if matches {
+        table.add_row(row![
+            address,
+            format_label(account),
+            format_delegation(account)
+        ]);
+    }
+}
+
+fn format_delegation(account: &Delegation) -> String {
+    format!(
+        "version: {}\nmaster: {}\nrepresentative: {}\nauthorised: {}\npermissions: {:#x}\nvalid from: {}\nvalid until: {}\nallowed programs: {}\nnamespace: {}\nuses: {}\nrequested: {}\noriginal master: {}\nrotation allowed: {}\nrent recipient: {}\nsession: {}\nrole: {}",
+        account.version,
+        account.master,
+        account.representative,
+        account.authorised,
+        account.permissions,
+        format_timestamp(account.valid_from),
+        format_timestamp(account.valid_until),
+        format_allowed_programs(&account.allowed_programs),
+        account
+            .namespace
+            .map_or_else(|| String::from("-"), |namespace| namespace.to_string()),
+        account.max_uses.map_or_else(
+            || account.uses.to_string(),
+            |max_uses| format!("{}/{}", account.uses, max_uses)
+        ),
+        account.requested,
+        account
+            .original_master
+            .map_or_else(|| String::from("-"), |master| master.to_string()),
+        account.rotation_allowed,
+        account.refund_recipient(),
+        account.session,
+        account
+            .role
+            .map_or_else(|| String::from("-"), |role| role.to_string())
+    )
+}
+
+fn format_label(account: &Delegation) -> String {
+    match (account.label.is_empty(), account.metadata_uri.is_empty()) {
+        (true, true) => String::from("-"),
+        (false, true) => account.label.clone(),
+        (true, false) => account.metadata_uri.clone(),
+        (false, false) => format!("{}\n{}", account.label, account.metadata_uri),
+    }
+}
+
+fn format_allowed_programs(allowed_programs: &[Pubkey]) -> String {
+    if allowed_programs.is_empty() {
+        return String::from("any");
     }
+    allowed_programs
+        .iter()
+        .map(|program_id| program_id.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn format_timestamp(timestamp: Option<i64>) -> String {
+    timestamp.map_or_else(|| String::from("-"), |timestamp| timestamp.to_string())
 }
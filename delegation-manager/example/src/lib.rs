@@ -26,7 +26,8 @@ pub mod example {
             check_authorization(
                 &ctx.accounts.authority.to_account_info(),
                 &ctx.accounts.payer.to_account_info(),
-                ctx.remaining_accounts.iter().next(),
+                ctx.remaining_accounts.first(),
+                ctx.remaining_accounts.get(1),
             )?;
         }
         counter.count += 1;
@@ -0,0 +1,195 @@
+//! # Unique Delegation Manager interface
+//!
+//! Instruction builders for the Unique Delegation Manager program, for programs and clients
+//! that don't depend on anchor-lang. Programs written with native solana_program can verify
+//! a delegation by invoking the read-only assert_authorized instruction.
+
+use solana_program::{
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
+    program::invoke,
+    pubkey::Pubkey,
+};
+
+solana_program::declare_id!("UPLdquGEBVnVK5TmccSue5gyPkxSRT4poezHShoEzg8");
+
+pub const AUTHORIZE_SEED: &[u8] = b"authorize";
+pub const MASTER_SETTINGS_SEED: &[u8] = b"master_settings";
+
+/// Permission allowing the representative to trade on behalf of the master
+pub const PERMISSION_TRADE: u64 = 1 << 0;
+/// Permission allowing the representative to claim rewards on behalf of the master
+pub const PERMISSION_CLAIM: u64 = 1 << 1;
+/// Permission allowing the representative to stake on behalf of the master
+pub const PERMISSION_STAKE: u64 = 1 << 2;
+/// Permission allowing the representative to vote on behalf of the master
+pub const PERMISSION_VOTE: u64 = 1 << 3;
+/// Permission allowing the representative to withdraw assets on behalf of the master
+pub const PERMISSION_WITHDRAW: u64 = 1 << 4;
+/// Grants every current and future permission
+pub const PERMISSION_ALL: u64 = u64::MAX;
+
+/// Anchor discriminator of the assert_authorized instruction, the first 8 bytes of
+/// sha256("global:assert_authorized")
+pub const ASSERT_AUTHORIZED_DISCRIMINATOR: [u8; 8] = [4, 18, 30, 98, 239, 94, 43, 221];
+
+/// Creates an assert_authorized instruction, which fails unless the Delegation authorises the
+/// representative to act for the master in the given scope, namespace and calling program.
+/// The master is always authorised to act for itself. Delegations whose master was transferred
+/// aren't derived from the current master, so the delegation address is passed explicitly.
+/// Delegations holding a Role are only honoured with the address of the Role passed along.
+pub fn assert_authorized(
+    master: &Pubkey,
+    representative: &Pubkey,
+    delegation: &Pubkey,
+    role: Option<&Pubkey>,
+    namespace: Option<&Pubkey>,
+    calling_program: Option<&Pubkey>,
+    scope: u64,
+) -> Instruction {
+    let mut data = ASSERT_AUTHORIZED_DISCRIMINATOR.to_vec();
+    push_option_pubkey(&mut data, namespace);
+    push_option_pubkey(&mut data, calling_program);
+    data.extend_from_slice(&scope.to_le_bytes());
+
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*master, false),
+        AccountMeta::new_readonly(*representative, false),
+        AccountMeta::new_readonly(*delegation, false),
+        AccountMeta::new_readonly(get_master_settings_address(master), false),
+    ];
+    // the Role is read by the program as its remaining account
+    if let Some(role) = role {
+        accounts.push(AccountMeta::new_readonly(*role, false));
+    }
+
+    Instruction {
+        program_id: ID,
+        accounts,
+        data,
+    }
+}
+
+/// Invokes the assert_authorized instruction from within a program. The delegation_program is
+/// the Delegation Manager program account, and the master_settings the MasterSettings account
+/// derived from the master (get_master_settings_address), which may not exist. The role is the
+/// Role account of the Delegation, if it holds one.
+#[allow(clippy::too_many_arguments)]
+pub fn invoke_assert_authorized<'info>(
+    delegation_program: &AccountInfo<'info>,
+    master: &AccountInfo<'info>,
+    representative: &AccountInfo<'info>,
+    delegation: &AccountInfo<'info>,
+    master_settings: &AccountInfo<'info>,
+    role: Option<&AccountInfo<'info>>,
+    namespace: Option<&Pubkey>,
+    calling_program: Option<&Pubkey>,
+    scope: u64,
+) -> ProgramResult {
+    let mut account_infos = vec![
+        master.clone(),
+        representative.clone(),
+        delegation.clone(),
+        master_settings.clone(),
+        delegation_program.clone(),
+    ];
+    account_infos.extend(role.cloned());
+    invoke(
+        &assert_authorized(
+            master.key,
+            representative.key,
+            delegation.key,
+            role.map(|role| role.key),
+            namespace,
+            calling_program,
+            scope,
+        ),
+        &account_infos,
+    )
+}
+
+/// Serializes an optional Pubkey the way Borsh does
+fn push_option_pubkey(data: &mut Vec<u8>, pubkey: Option<&Pubkey>) {
+    match pubkey {
+        Some(pubkey) => {
+            data.push(1);
+            data.extend_from_slice(pubkey.as_ref());
+        }
+        None => data.push(0),
+    }
+}
+
+pub fn get_delegation_address(master: &Pubkey, representative: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[AUTHORIZE_SEED, master.as_ref(), representative.as_ref()],
+        &ID,
+    )
+    .0
+}
+
+pub fn get_namespaced_delegation_address(
+    master: &Pubkey,
+    representative: &Pubkey,
+    namespace: &Pubkey,
+) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            AUTHORIZE_SEED,
+            master.as_ref(),
+            representative.as_ref(),
+            namespace.as_ref(),
+        ],
+        &ID,
+    )
+    .0
+}
+
+pub fn get_master_settings_address(master: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[MASTER_SETTINGS_SEED, master.as_ref()], &ID).0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::{InstructionData, ToAccountMetas};
+
+    #[test]
+    fn assert_authorized_matches_program() {
+        let master = Pubkey::new_unique();
+        let representative = Pubkey::new_unique();
+        let delegation = Pubkey::new_unique();
+        let namespace = Pubkey::new_unique();
+        let role = Pubkey::new_unique();
+
+        let instruction = assert_authorized(
+            &master,
+            &representative,
+            &delegation,
+            Some(&role),
+            Some(&namespace),
+            None,
+            PERMISSION_CLAIM,
+        );
+
+        assert_eq!(instruction.program_id, upl_delegation_manager::ID);
+        assert_eq!(
+            instruction.data,
+            upl_delegation_manager::instruction::AssertAuthorized {
+                namespace: Some(namespace),
+                calling_program: None,
+                scope: PERMISSION_CLAIM,
+            }
+            .data()
+        );
+        let mut accounts = upl_delegation_manager::accounts::AssertAuthorized {
+            master,
+            representative,
+            delegation,
+            master_settings: upl_delegation_manager::get_master_settings_address(&master),
+        }
+        .to_account_metas(None);
+        accounts.push(AccountMeta::new_readonly(role, false));
+        assert_eq!(instruction.accounts, accounts);
+    }
+}
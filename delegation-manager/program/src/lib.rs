@@ -4,23 +4,415 @@
 //! between 1-to-many wallets. Protocols that implement it can allow safe execution of numerous
 //! actions for users without exposing their assets to any risks.
 
-use anchor_lang::prelude::*;
+use anchor_lang::{
+    prelude::*,
+    solana_program::{
+        ed25519_program,
+        hash::{hash, Hash},
+        sysvar::instructions::{load_current_index_checked, load_instruction_at_checked},
+    },
+    system_program, Discriminator,
+};
 declare_id!("UPLdquGEBVnVK5TmccSue5gyPkxSRT4poezHShoEzg8");
 
 #[constant]
 pub const AUTHORIZE_SEED: &'static [u8] = b"authorize";
+#[constant]
+pub const ALLOWANCE_SEED: &'static [u8] = b"allowance";
+#[constant]
+pub const MASTER_INDEX_SEED: &'static [u8] = b"master_index";
+#[constant]
+pub const REPRESENTATIVE_INDEX_SEED: &'static [u8] = b"representative_index";
+#[constant]
+pub const MASTER_SETTINGS_SEED: &'static [u8] = b"master_settings";
+#[constant]
+pub const MASTER_TRANSFER_SEED: &'static [u8] = b"master_transfer";
+#[constant]
+pub const ROLE_SEED: &'static [u8] = b"role";
+
+/// Mint used to derive the Allowance tracking lamports spent from the master's wallet
+pub const LAMPORTS_MINT: Pubkey = Pubkey::new_from_array([0; 32]);
+
+/// Permission allowing the representative to trade on behalf of the master
+#[constant]
+pub const PERMISSION_TRADE: u64 = 1 << 0;
+/// Permission allowing the representative to claim rewards on behalf of the master
+#[constant]
+pub const PERMISSION_CLAIM: u64 = 1 << 1;
+/// Permission allowing the representative to stake on behalf of the master
+#[constant]
+pub const PERMISSION_STAKE: u64 = 1 << 2;
+/// Permission allowing the representative to vote on behalf of the master
+#[constant]
+pub const PERMISSION_VOTE: u64 = 1 << 3;
+/// Permission allowing the representative to withdraw assets on behalf of the master
+#[constant]
+pub const PERMISSION_WITHDRAW: u64 = 1 << 4;
+/// Grants every current and future permission
+#[constant]
+pub const PERMISSION_ALL: u64 = u64::MAX;
+
+/// Maximum number of times a delegation can be re-delegated down a chain
+#[constant]
+pub const MAX_DELEGATION_DEPTH: u8 = 4;
+
+/// Maximum number of seconds a session key delegation may last
+#[constant]
+pub const MAX_SESSION_DURATION: i64 = 7 * 24 * 60 * 60;
+
+/// Layout version of the Delegation accounts created by this program
+#[constant]
+pub const DELEGATION_VERSION: u8 = 2;
+/// Layout version of the Delegation accounts created before the layout was versioned
+pub const LEGACY_DELEGATION_VERSION: u8 = 1;
+
+/// Maximum number of programs a Delegation can be restricted to
+pub const MAX_ALLOWED_PROGRAMS: usize = 8;
+
+/// Maximum number of Delegations listed in a MasterIndex or RepresentativeIndex, which bounds
+/// the cost of removing one. Delegations created once an index is full aren't listed in it.
+pub const MAX_INDEX_ENTRIES: usize = 1024;
+
+/// Maximum length in bytes of the label of a Delegation
+pub const MAX_LABEL_LEN: usize = 32;
+
+/// Maximum length in bytes of the metadata URI of a Delegation
+pub const MAX_METADATA_URI_LEN: usize = 200;
+
+/// Maximum length in bytes of the name of a Role
+pub const MAX_ROLE_NAME_LEN: usize = 32;
+
 /// Unique program library's Delegation Manager program.
 #[program]
 pub mod delegation_manager {
     use super::*;
 
     /// Initializes delegate ix is used by a wallet to initialize the Delegation
-    /// account.
-    pub fn initialize_delegate(ctx: Context<InitializeDelegation>) -> Result<()> {
+    /// account under the given terms, labelled with the given metadata.
+    pub fn initialize_delegate(
+        ctx: Context<InitializeDelegation>,
+        terms: DelegationTerms,
+        metadata: DelegationMetadata,
+    ) -> Result<()> {
+        ctx.accounts.delegation.init(
+            ctx.accounts.master.key(),
+            ctx.accounts.representative.key(),
+            None,
+            ctx.accounts.payer.key(),
+            terms,
+        )?;
+        ctx.accounts.delegation.revocation_epoch = revocation_epoch(&ctx.accounts.master_settings)?;
+        ctx.accounts.delegation.set_metadata(metadata)?;
+        add_to_master_index(
+            &ctx.accounts.master_index,
+            *ctx.bumps.get("master_index").unwrap(),
+            &ctx.accounts.master.key(),
+            &ctx.accounts.representative.key(),
+            &ctx.accounts.delegation.key(),
+            &ctx.accounts.payer,
+            &ctx.accounts.system_program,
+        )?;
+        add_to_representative_index(
+            &ctx.accounts.representative_index,
+            *ctx.bumps.get("representative_index").unwrap(),
+            &ctx.accounts.representative.key(),
+            RepresentativeIndexEntry {
+                master: ctx.accounts.master.key(),
+                delegation: ctx.accounts.delegation.key(),
+                confirmed: false,
+            },
+            &ctx.accounts.payer,
+            &ctx.accounts.system_program,
+        )?;
+        emit_created(ctx.accounts.delegation.key(), &ctx.accounts.delegation)
+    }
+
+    /// Initialize delegates batch ix works like initialize delegate for several representatives
+    /// at once, under the same terms and metadata, e.g. when onboarding a fleet of bots. Each
+    /// representative is passed as a remaining account triple of the representative, its
+    /// Delegation PDA and its RepresentativeIndex.
+    pub fn initialize_delegates_batch<'a, 'b, 'c, 'info>(
+        ctx: Context<'a, 'b, 'c, 'info, InitializeDelegationsBatch<'info>>,
+        terms: DelegationTerms,
+        metadata: DelegationMetadata,
+    ) -> Result<()> {
+        let master = ctx.accounts.master.key();
+        let epoch = revocation_epoch(&ctx.accounts.master_settings)?;
+        let remaining_accounts = ctx.remaining_accounts.chunks_exact(3);
+        require!(
+            !ctx.remaining_accounts.is_empty() && remaining_accounts.remainder().is_empty(),
+            DelegationError::InvalidRemainingAccounts
+        );
+
+        for accounts in remaining_accounts {
+            let (representative, delegation_info, representative_index) =
+                (accounts[0].key(), &accounts[1], &accounts[2]);
+            let (address, bump) = Pubkey::find_program_address(
+                &get_delegation_address_seeds(&master, &representative),
+                &ID,
+            );
+            require_keys_eq!(delegation_info.key(), address, ErrorCode::ConstraintSeeds);
+            let (index_address, index_bump) = Pubkey::find_program_address(
+                &get_representative_index_address_seeds(&representative),
+                &ID,
+            );
+            require_keys_eq!(
+                representative_index.key(),
+                index_address,
+                ErrorCode::ConstraintSeeds
+            );
+
+            let mut delegation = Delegation::default();
+            delegation.init(
+                master,
+                representative,
+                None,
+                ctx.accounts.payer.key(),
+                terms.clone(),
+            )?;
+            delegation.revocation_epoch = epoch;
+            delegation.set_metadata(metadata.clone())?;
+            create_program_account(
+                delegation_info,
+                &[
+                    AUTHORIZE_SEED,
+                    master.as_ref(),
+                    representative.as_ref(),
+                    &[bump],
+                ],
+                &delegation,
+                Delegation::SPACE,
+                &ctx.accounts.payer,
+                &ctx.accounts.system_program,
+            )?;
+
+            add_to_master_index(
+                &ctx.accounts.master_index,
+                *ctx.bumps.get("master_index").unwrap(),
+                &master,
+                &representative,
+                &address,
+                &ctx.accounts.payer,
+                &ctx.accounts.system_program,
+            )?;
+            add_to_representative_index(
+                representative_index,
+                index_bump,
+                &representative,
+                RepresentativeIndexEntry {
+                    master,
+                    delegation: address,
+                    confirmed: false,
+                },
+                &ctx.accounts.payer,
+                &ctx.accounts.system_program,
+            )?;
+            emit_created(address, &delegation)?;
+        }
+        Ok(())
+    }
+
+    /// Initialize session delegate ix is used by the master to delegate to an ephemeral session
+    /// key it generated, e.g. to let a game or trading UI act without a wallet popup for each
+    /// action. The Delegation is confirmed right away, as the master holds the session key, and
+    /// needs to expire within MAX_SESSION_DURATION. Once expired, anyone can close it with
+    /// close_expired_session, returning the rent to the master. Session delegations are only
+    /// listed in the index of the master, the session key isn't meant to outlive them.
+    pub fn initialize_session_delegate(
+        ctx: Context<InitializeSessionDelegation>,
+        terms: DelegationTerms,
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        require!(
+            matches!(terms.valid_until, Some(valid_until) if valid_until <= now + MAX_SESSION_DURATION),
+            DelegationError::InvalidSessionDuration
+        );
+        let delegation = &mut ctx.accounts.delegation;
+        delegation.init(
+            ctx.accounts.master.key(),
+            ctx.accounts.session_key.key(),
+            None,
+            ctx.accounts.master.key(),
+            terms,
+        )?;
+        delegation.authorised = true;
+        delegation.session = true;
+        delegation.revocation_epoch = revocation_epoch(&ctx.accounts.master_settings)?;
+        add_to_master_index(
+            &ctx.accounts.master_index,
+            *ctx.bumps.get("master_index").unwrap(),
+            &delegation.master,
+            &delegation.representative,
+            &delegation.key(),
+            &ctx.accounts.master,
+            &ctx.accounts.system_program,
+        )?;
+
+        emit_created(delegation.key(), delegation)?;
+        emit!(DelegationConfirmed {
+            master: delegation.master,
+            representative: delegation.representative,
+            delegation: delegation.key(),
+            confirmed_by: delegation.master,
+            slot: delegation.created_slot,
+        });
+        Ok(())
+    }
+
+    /// Close expired session ix closes a session key Delegation once it expired, returning the
+    /// rent to its rent recipient, the master who created it. Anyone can invoke it, e.g. a crank
+    /// cleaning up after a game session.
+    pub fn close_expired_session(ctx: Context<CloseExpiredSession>) -> Result<()> {
+        let delegation = &ctx.accounts.delegation;
+        require_keys_eq!(
+            ctx.accounts.rent_recipient.key(),
+            delegation.refund_recipient(),
+            DelegationError::WrongRentRecipient
+        );
+        let now = Clock::get()?.unix_timestamp;
+        require!(delegation.session, DelegationError::NotSession);
+        require!(
+            matches!(delegation.valid_until, Some(valid_until) if now >= valid_until),
+            DelegationError::SessionNotExpired
+        );
+        remove_from_master_index(&ctx.accounts.master_index, &delegation.key())?;
+
+        emit!(SessionClosed {
+            master: delegation.master,
+            representative: delegation.representative,
+            delegation: delegation.key(),
+            slot: Clock::get()?.slot,
+        });
+        Ok(())
+    }
+
+    /// Initialize namespaced delegate ix works like initialize delegate, but derives the
+    /// Delegation from the namespace as well, so a master can hold separate delegations to
+    /// the same representative for different protocols.
+    pub fn initialize_namespaced_delegate(
+        ctx: Context<InitializeNamespacedDelegation>,
+        namespace: Pubkey,
+        terms: DelegationTerms,
+        metadata: DelegationMetadata,
+    ) -> Result<()> {
+        ctx.accounts.delegation.init(
+            ctx.accounts.master.key(),
+            ctx.accounts.representative.key(),
+            Some(namespace),
+            ctx.accounts.payer.key(),
+            terms,
+        )?;
+        ctx.accounts.delegation.revocation_epoch = revocation_epoch(&ctx.accounts.master_settings)?;
+        ctx.accounts.delegation.set_metadata(metadata)?;
+        add_to_master_index(
+            &ctx.accounts.master_index,
+            *ctx.bumps.get("master_index").unwrap(),
+            &ctx.accounts.master.key(),
+            &ctx.accounts.representative.key(),
+            &ctx.accounts.delegation.key(),
+            &ctx.accounts.payer,
+            &ctx.accounts.system_program,
+        )?;
+        add_to_representative_index(
+            &ctx.accounts.representative_index,
+            *ctx.bumps.get("representative_index").unwrap(),
+            &ctx.accounts.representative.key(),
+            RepresentativeIndexEntry {
+                master: ctx.accounts.master.key(),
+                delegation: ctx.accounts.delegation.key(),
+                confirmed: false,
+            },
+            &ctx.accounts.payer,
+            &ctx.accounts.system_program,
+        )?;
+        emit_created(ctx.accounts.delegation.key(), &ctx.accounts.delegation)
+    }
+
+    /// Initialize sub delegate ix is used by a confirmed representative to re-delegate its
+    /// authority to another wallet, with equal or narrower permissions. The child Delegation
+    /// references its parent and is only honoured through check_authorization_chain, so
+    /// cancelling the parent invalidates the whole subtree.
+    pub fn initialize_sub_delegate(
+        ctx: Context<InitializeSubDelegation>,
+        terms: DelegationTerms,
+    ) -> Result<()> {
+        let parent = &ctx.accounts.parent_delegation;
+        require!(
+            ctx.accounts.delegator.key() == parent.representative,
+            DelegationError::WrongRepresentative
+        );
+        require!(parent.authorised, DelegationError::NotAuthorized);
+        parent.check_validity(Clock::get()?.unix_timestamp)?;
+        require!(parent.max_uses.is_none(), DelegationError::UsageLimited);
+        require!(parent.role.is_none(), DelegationError::RoleSubDelegation);
+        require!(
+            parent.depth < MAX_DELEGATION_DEPTH,
+            DelegationError::DelegationChainTooDeep
+        );
+        require!(
+            parent.has_permissions(terms.permissions),
+            DelegationError::MissingPermission
+        );
+
         let delegation = &mut ctx.accounts.delegation;
-        delegation.master = ctx.accounts.master.key();
-        delegation.representative = ctx.accounts.representative.key();
-        delegation.authorised = false;
+        delegation.init(
+            ctx.accounts.delegator.key(),
+            ctx.accounts.representative.key(),
+            parent.namespace,
+            ctx.accounts.payer.key(),
+            terms,
+        )?;
+        delegation.parent = Some(parent.key());
+        delegation.parent_created_slot = parent.created_slot;
+        delegation.depth = parent.depth + 1;
+        add_to_master_index(
+            &ctx.accounts.master_index,
+            *ctx.bumps.get("master_index").unwrap(),
+            &ctx.accounts.delegator.key(),
+            &ctx.accounts.representative.key(),
+            &ctx.accounts.delegation.key(),
+            &ctx.accounts.payer,
+            &ctx.accounts.system_program,
+        )?;
+        add_to_representative_index(
+            &ctx.accounts.representative_index,
+            *ctx.bumps.get("representative_index").unwrap(),
+            &ctx.accounts.representative.key(),
+            RepresentativeIndexEntry {
+                master: ctx.accounts.delegator.key(),
+                delegation: ctx.accounts.delegation.key(),
+                confirmed: false,
+            },
+            &ctx.accounts.payer,
+            &ctx.accounts.system_program,
+        )?;
+        emit_created(ctx.accounts.delegation.key(), &ctx.accounts.delegation)
+    }
+
+    /// Set allowed programs ix is used by the master to replace the list of programs
+    /// which honour the delegation. An empty list lifts the restriction.
+    pub fn set_allowed_programs(
+        ctx: Context<SetAllowedPrograms>,
+        allowed_programs: Vec<Pubkey>,
+    ) -> Result<()> {
+        validate_allowed_programs(&allowed_programs)?;
+        ctx.accounts.delegation.allowed_programs = allowed_programs;
+        Ok(())
+    }
+
+    /// Update delegation metadata ix is used by the master to replace the label and metadata
+    /// URI describing the delegation, e.g. which bot or service the representative is.
+    pub fn update_delegation_metadata(
+        ctx: Context<UpdateDelegationMetadata>,
+        metadata: DelegationMetadata,
+    ) -> Result<()> {
+        ctx.accounts.delegation.set_metadata(metadata)
+    }
+
+    /// Set rotation allowed ix is used by the master to let the representative rotate to a new
+    /// key with rotate_representative without the master signing, or to require it again.
+    pub fn set_rotation_allowed(ctx: Context<SetRotationAllowed>, allowed: bool) -> Result<()> {
+        ctx.accounts.delegation.rotation_allowed = allowed;
         Ok(())
     }
 
@@ -33,24 +425,164 @@ pub mod delegation_manager {
             DelegationError::WrongRepresentative
         );
         require!(!delegation.authorised, DelegationError::AlreadyAuthorised);
+        require!(!delegation.requested, DelegationError::DelegationRequested);
+        delegation.authorised = true;
+        confirm_in_representative_index(&ctx.accounts.representative_index, &delegation.key())?;
+
+        emit!(DelegationConfirmed {
+            master: delegation.master,
+            representative: delegation.representative,
+            delegation: delegation.key(),
+            confirmed_by: delegation.representative,
+            slot: Clock::get()?.slot,
+        });
+        Ok(())
+    }
+
+    /// Confirm delegate with signature ix confirms the delegation like confirm_delegate does,
+    /// with the representative's ed25519 signature over get_confirmation_message instead of its
+    /// transaction signature, so that anyone can submit it for cold or custodial keys. The
+    /// signature is verified by an Ed25519 program instruction right before this one.
+    pub fn confirm_delegate_with_signature(
+        ctx: Context<ConfirmDelegationWithSignature>,
+    ) -> Result<()> {
+        let delegation = &mut ctx.accounts.delegation;
+        require!(!delegation.authorised, DelegationError::AlreadyAuthorised);
+        require!(!delegation.requested, DelegationError::DelegationRequested);
+        verify_ed25519_signature(
+            &ctx.accounts.instructions,
+            &delegation.representative,
+            &get_confirmation_message(
+                &delegation.key(),
+                &delegation.master,
+                &delegation.representative,
+                delegation.created_slot,
+            ),
+        )?;
+        delegation.authorised = true;
+        confirm_in_representative_index(&ctx.accounts.representative_index, &delegation.key())?;
+
+        emit!(DelegationConfirmed {
+            master: delegation.master,
+            representative: delegation.representative,
+            delegation: delegation.key(),
+            confirmed_by: delegation.representative,
+            slot: Clock::get()?.slot,
+        });
+        Ok(())
+    }
+
+    /// Request delegation ix is used by a wallet to ask the master to be its representative
+    /// under the given terms. The representative pays the rent for the Delegation account,
+    /// which becomes authorised once the master approves the request.
+    pub fn request_delegation(
+        ctx: Context<RequestDelegation>,
+        terms: DelegationTerms,
+    ) -> Result<()> {
+        let delegation = &mut ctx.accounts.delegation;
+        delegation.init(
+            ctx.accounts.master.key(),
+            ctx.accounts.representative.key(),
+            None,
+            ctx.accounts.representative.key(),
+            terms,
+        )?;
+        delegation.requested = true;
+        // listed in the MasterIndex once approved, so requests can't fill the master's index
+        add_to_representative_index(
+            &ctx.accounts.representative_index,
+            *ctx.bumps.get("representative_index").unwrap(),
+            &ctx.accounts.representative.key(),
+            RepresentativeIndexEntry {
+                master: ctx.accounts.master.key(),
+                delegation: ctx.accounts.delegation.key(),
+                confirmed: false,
+            },
+            &ctx.accounts.representative,
+            &ctx.accounts.system_program,
+        )?;
+        emit_created(ctx.accounts.delegation.key(), &ctx.accounts.delegation)
+    }
+
+    /// Approve delegation request ix is used by the master to accept the request of the
+    /// representative by setting the authorised flag to true. The Delegation is listed in the
+    /// MasterIndex only then, at the master's expense.
+    pub fn approve_delegation_request(ctx: Context<ApproveDelegationRequest>) -> Result<()> {
+        let delegation = &mut ctx.accounts.delegation;
+        require!(delegation.requested, DelegationError::NotRequested);
+        require!(!delegation.authorised, DelegationError::AlreadyAuthorised);
         delegation.authorised = true;
+        delegation.revocation_epoch = revocation_epoch(&ctx.accounts.master_settings)?;
+        confirm_in_representative_index(&ctx.accounts.representative_index, &delegation.key())?;
+        add_to_master_index(
+            &ctx.accounts.master_index,
+            *ctx.bumps.get("master_index").unwrap(),
+            &delegation.master,
+            &delegation.representative,
+            &delegation.key(),
+            &ctx.accounts.master,
+            &ctx.accounts.system_program,
+        )?;
+
+        emit!(DelegationConfirmed {
+            master: delegation.master,
+            representative: delegation.representative,
+            delegation: delegation.key(),
+            confirmed_by: delegation.master,
+            slot: Clock::get()?.slot,
+        });
+        Ok(())
+    }
+
+    /// Decline delegate ix is used by the representative to refuse a pending delegation.
+    /// The Delegation account is erased, the rent SOLs go back to whoever paid for it and a
+    /// DelegationDeclined event is emitted so it can be told apart from a cancellation.
+    pub fn decline_delegate(ctx: Context<DeclineDelegation>) -> Result<()> {
+        let delegation = &ctx.accounts.delegation;
+        require!(!delegation.authorised, DelegationError::AlreadyAuthorised);
+        require!(!delegation.requested, DelegationError::DelegationRequested);
+        remove_from_master_index(&ctx.accounts.master_index, &delegation.key())?;
+        remove_from_representative_index(&ctx.accounts.representative_index, &delegation.key())?;
+
+        emit!(DelegationDeclined {
+            master: delegation.master,
+            representative: delegation.representative,
+            delegation: delegation.key(),
+            slot: Clock::get()?.slot,
+        });
         Ok(())
     }
 
     /// Cancel delegate is used to revoke the authorisation given to the representative by
     /// erasing the Delegation account. It can be invoked by both master and representative,
-    /// but the rent SOLs go to the master account.
+    /// and the rent SOLs go back to whoever paid for the account, its rent recipient.
     pub fn cancel_delegate<'a, 'b, 'c, 'info>(
         ctx: Context<'a, 'b, 'c, 'info, CancelDelegation<'info>>,
     ) -> Result<()> {
-        let delegation = &mut ctx.accounts.delegation;
+        let delegation_info = &ctx.accounts.delegation;
+        let delegation = load_delegation(delegation_info)?;
+        require_keys_eq!(
+            ctx.accounts.rent_recipient.key(),
+            delegation.refund_recipient(),
+            DelegationError::WrongRentRecipient
+        );
+        require_keys_eq!(
+            ctx.accounts.master_index.key(),
+            get_master_index_address(&delegation.master),
+            ErrorCode::ConstraintSeeds
+        );
+        require_keys_eq!(
+            ctx.accounts.representative_index.key(),
+            get_representative_index_address(&delegation.representative),
+            ErrorCode::ConstraintSeeds
+        );
         let remaining_accounts = &mut ctx.remaining_accounts.iter();
         let master = remaining_accounts
             .next()
-            .expect("Expected master as remaining account");
+            .ok_or(DelegationError::InvalidRemainingAccounts)?;
         let representative = remaining_accounts
             .next()
-            .expect("Expected representative as remaining account");
+            .ok_or(DelegationError::InvalidRemainingAccounts)?;
         require!(
             master.key() == delegation.master,
             DelegationError::WrongMaster
@@ -64,8 +596,545 @@ pub mod delegation_manager {
             DelegationError::WrongSigner
         );
 
-        delegation.close(master.to_account_info())?;
+        cancel_delegation(
+            &delegation,
+            &delegation_info.key(),
+            &if master.is_signer {
+                master.key()
+            } else {
+                representative.key()
+            },
+            &ctx.accounts.master_index,
+            &ctx.accounts.representative_index,
+        )?;
+        close_account(delegation_info, &ctx.accounts.rent_recipient)
+    }
+
+    /// Cancel delegates batch ix works like cancel delegate for several delegations of the
+    /// signer at once, whether it is their master or their representative. Each delegation is
+    /// passed as a remaining account triple of the Delegation, its rent recipient and the index
+    /// of the other party: the RepresentativeIndex of the representative if the signer is the
+    /// master, the MasterIndex of the master otherwise.
+    pub fn cancel_delegates_batch<'a, 'b, 'c, 'info>(
+        ctx: Context<'a, 'b, 'c, 'info, CancelDelegationsBatch<'info>>,
+    ) -> Result<()> {
+        let signer = ctx.accounts.signer.key();
+        let remaining_accounts = ctx.remaining_accounts.chunks_exact(3);
+        require!(
+            !ctx.remaining_accounts.is_empty() && remaining_accounts.remainder().is_empty(),
+            DelegationError::InvalidRemainingAccounts
+        );
+
+        for accounts in remaining_accounts {
+            let (delegation_info, rent_recipient, index) =
+                (&accounts[0], &accounts[1], &accounts[2]);
+            let delegation = load_delegation(delegation_info)?;
+            require_keys_eq!(
+                rent_recipient.key(),
+                delegation.refund_recipient(),
+                DelegationError::WrongRentRecipient
+            );
+            let (master_index, representative_index) = if delegation.master == signer {
+                require_keys_eq!(
+                    index.key(),
+                    get_representative_index_address(&delegation.representative),
+                    ErrorCode::ConstraintSeeds
+                );
+                (&*ctx.accounts.master_index, index)
+            } else {
+                require_keys_eq!(
+                    index.key(),
+                    get_master_index_address(&delegation.master),
+                    ErrorCode::ConstraintSeeds
+                );
+                (index, &*ctx.accounts.representative_index)
+            };
+
+            cancel_delegation(
+                &delegation,
+                &delegation_info.key(),
+                &signer,
+                master_index,
+                representative_index,
+            )?;
+            close_account(delegation_info, rent_recipient)?;
+        }
+        Ok(())
+    }
+
+    /// Rotate representative ix is used by the representative to move its Delegation to a new
+    /// key, signing along, e.g. when rotating the hot key of a bot. The new Delegation, derived
+    /// like the current one, carries over its terms, confirmation and counters, and the current
+    /// one is closed. The master needs to sign as well, unless it allowed rotations with
+    /// set_rotation_allowed. The representative pays the rent of the new Delegation and gets
+    /// the rent of the current one back, so whoever paid for it is still refunded on cancel.
+    /// Allowances and sub-delegations of the current Delegation aren't carried over.
+    pub fn rotate_representative(ctx: Context<RotateRepresentative>) -> Result<()> {
+        let delegation = &ctx.accounts.delegation;
+        require!(
+            ctx.accounts.master.is_signer || delegation.rotation_allowed,
+            DelegationError::RotationNotAllowed
+        );
+        let new_representative = ctx.accounts.new_representative.key();
+        let new_delegation = &ctx.accounts.new_delegation;
+
+        let seed_key = delegation.seed_key();
+        let namespace = delegation.seed_namespace();
+        let mut seeds: Vec<&[u8]> = vec![
+            AUTHORIZE_SEED,
+            seed_key.as_ref(),
+            new_representative.as_ref(),
+        ];
+        if let Some(namespace) = &namespace {
+            seeds.push(namespace.as_ref());
+        }
+        let (address, bump) = Pubkey::find_program_address(&seeds, &ID);
+        require_keys_eq!(new_delegation.key(), address, ErrorCode::ConstraintSeeds);
+        let bump = [bump];
+        seeds.push(&bump);
+        create_program_account(
+            new_delegation,
+            &seeds,
+            &Delegation {
+                representative: new_representative,
+                ..Delegation::clone(delegation)
+            },
+            Delegation::SPACE,
+            &ctx.accounts.representative,
+            &ctx.accounts.system_program,
+        )?;
+
+        remove_from_master_index(&ctx.accounts.master_index, &delegation.key())?;
+        add_to_master_index(
+            &ctx.accounts.master_index,
+            *ctx.bumps.get("master_index").unwrap(),
+            &delegation.master,
+            &new_representative,
+            &new_delegation.key(),
+            &ctx.accounts.representative,
+            &ctx.accounts.system_program,
+        )?;
+        remove_from_representative_index(&ctx.accounts.representative_index, &delegation.key())?;
+        add_to_representative_index(
+            &ctx.accounts.new_representative_index,
+            *ctx.bumps.get("new_representative_index").unwrap(),
+            &new_representative,
+            RepresentativeIndexEntry {
+                master: delegation.master,
+                delegation: new_delegation.key(),
+                confirmed: delegation.authorised,
+            },
+            &ctx.accounts.representative,
+            &ctx.accounts.system_program,
+        )?;
+
+        emit!(RepresentativeRotated {
+            master: delegation.master,
+            representative: delegation.representative,
+            new_representative,
+            delegation: delegation.key(),
+            new_delegation: new_delegation.key(),
+            slot: Clock::get()?.slot,
+        });
+        Ok(())
+    }
+
+    /// Migrate delegation ix reallocates a Delegation created with the legacy layout to the
+    /// current one. It keeps its master, representative and confirmation, is granted all
+    /// permissions without restrictions like legacy delegations were, and is listed in the
+    /// indexes of its master and representative. Legacy delegations didn't record their creation
+    /// slot, so the slot of the migration is recorded instead. Anyone can invoke it, the payer
+    /// funds the additional rent.
+    pub fn migrate_delegation(ctx: Context<MigrateDelegation>) -> Result<()> {
+        let delegation_info = ctx.accounts.delegation.to_account_info();
+        require!(
+            delegation_info.data_len() == Delegation::LEGACY_SPACE,
+            DelegationError::AlreadyMigrated
+        );
+        let delegation =
+            Delegation::try_deserialize_versioned(&delegation_info.try_borrow_data()?)?;
+        require_keys_eq!(
+            delegation_info.key(),
+            get_delegation_address(&delegation.master, &delegation.representative),
+            ErrorCode::ConstraintSeeds
+        );
+        let (master_index, bump) =
+            Pubkey::find_program_address(&get_master_index_address_seeds(&delegation.master), &ID);
+        require_keys_eq!(
+            ctx.accounts.master_index.key(),
+            master_index,
+            ErrorCode::ConstraintSeeds
+        );
+        add_to_master_index(
+            &ctx.accounts.master_index,
+            bump,
+            &delegation.master,
+            &delegation.representative,
+            &delegation_info.key(),
+            &ctx.accounts.payer,
+            &ctx.accounts.system_program,
+        )?;
+        let (representative_index, bump) = Pubkey::find_program_address(
+            &get_representative_index_address_seeds(&delegation.representative),
+            &ID,
+        );
+        require_keys_eq!(
+            ctx.accounts.representative_index.key(),
+            representative_index,
+            ErrorCode::ConstraintSeeds
+        );
+        add_to_representative_index(
+            &ctx.accounts.representative_index,
+            bump,
+            &delegation.representative,
+            RepresentativeIndexEntry {
+                master: delegation.master,
+                delegation: delegation_info.key(),
+                confirmed: delegation.authorised,
+            },
+            &ctx.accounts.payer,
+            &ctx.accounts.system_program,
+        )?;
+
+        grow_account(
+            &delegation_info,
+            &ctx.accounts.payer,
+            &ctx.accounts.system_program,
+            Delegation::SPACE,
+        )?;
+
+        let mut data = delegation_info.try_borrow_mut_data()?;
+        let mut writer: &mut [u8] = &mut data;
+        Delegation {
+            version: DELEGATION_VERSION,
+            created_slot: Clock::get()?.slot,
+            ..delegation
+        }
+        .try_serialize(&mut writer)
+    }
+
+    /// Use delegate ix is invoked by integrating programs, signed by the representative, to
+    /// verify the delegation like check_authorization_for_program does and count the use.
+    /// Usage-limited delegations are cancelled once used up, if their rent goes back to the
+    /// master. Sponsored ones are left to be closed with cancel_delegate, which refunds the sponsor.
+    pub fn use_delegate(
+        ctx: Context<UseDelegation>,
+        namespace: Option<Pubkey>,
+        calling_program: Option<Pubkey>,
+        scope: u64,
+    ) -> Result<()> {
+        let delegation = &mut ctx.accounts.delegation;
+        // the Role, if any, is passed as remaining account and only applies to this check
+        let mut terms = Delegation::clone(delegation);
+        apply_role(&mut terms, ctx.remaining_accounts.first())?;
+        terms.verify(
+            &ctx.accounts.master.key(),
+            &ctx.accounts.representative.key(),
+            namespace.as_ref(),
+            calling_program.as_ref(),
+        )?;
+        check_master_settings(
+            &ctx.accounts.master_settings,
+            &delegation.master,
+            delegation,
+        )?;
+        require!(delegation.parent.is_none(), DelegationError::SubDelegation);
+        require!(
+            terms.has_permissions(scope),
+            DelegationError::MissingPermission
+        );
+
+        if let Some(max_uses) = delegation.max_uses {
+            require!(delegation.uses < max_uses, DelegationError::UsedUp);
+            delegation.uses += 1;
+            if delegation.uses >= max_uses && delegation.refund_recipient() == delegation.master {
+                cancel_delegation(
+                    delegation,
+                    &delegation.key(),
+                    &ctx.accounts.representative.key(),
+                    &ctx.accounts.master_index,
+                    &ctx.accounts.representative_index,
+                )?;
+                delegation.close(ctx.accounts.master.to_account_info())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Assert authorized ix lets programs that can't link the check functions, e.g. native
+    /// solana_program ones, verify the delegation by CPI like check_namespaced_authorization
+    /// does. It doesn't modify any account, and fails if the representative isn't authorised.
+    /// The Role referenced by the delegation, if any, is passed as remaining account.
+    pub fn assert_authorized(
+        ctx: Context<AssertAuthorized>,
+        namespace: Option<Pubkey>,
+        calling_program: Option<Pubkey>,
+        scope: u64,
+    ) -> Result<()> {
+        if let Some(delegation) = load_authorized_delegation(
+            &ctx.accounts.master,
+            &ctx.accounts.representative,
+            Some(&ctx.accounts.delegation),
+            Some(&ctx.accounts.master_settings),
+            ctx.remaining_accounts.first(),
+            namespace.as_ref(),
+            calling_program.as_ref(),
+        )? {
+            require!(
+                delegation.has_permissions(scope),
+                DelegationError::MissingPermission
+            );
+        }
+        Ok(())
+    }
+
+    /// Assert authorized chain ix mirrors assert authorized for sub-delegations, verifying the
+    /// chain like check_authorization_chain does. The chain of Delegation accounts, from the one
+    /// created by the master to the one held by the representative, is passed as remaining accounts.
+    pub fn assert_authorized_chain(
+        ctx: Context<AssertAuthorizedChain>,
+        namespace: Option<Pubkey>,
+        calling_program: Option<Pubkey>,
+        scope: u64,
+        max_depth: u8,
+    ) -> Result<()> {
+        check_authorization_chain(
+            &ctx.accounts.master,
+            &ctx.accounts.representative,
+            ctx.remaining_accounts,
+            &ctx.accounts.master_settings,
+            namespace.as_ref(),
+            calling_program.as_ref(),
+            scope,
+            max_depth,
+        )
+    }
+
+    /// Create role ix is used by a master to define a named set of permissions and allowed
+    /// programs, e.g. "operator" or "auditor", which its delegations can reference with
+    /// assign_role.
+    pub fn create_role(
+        ctx: Context<CreateRole>,
+        name: String,
+        permissions: u64,
+        allowed_programs: Vec<Pubkey>,
+    ) -> Result<()> {
+        require!(
+            name.len() <= MAX_ROLE_NAME_LEN,
+            DelegationError::RoleNameTooLong
+        );
+        let role = &mut ctx.accounts.role;
+        role.authority = ctx.accounts.authority.key();
+        role.name = name;
+        role.set_terms(permissions, allowed_programs)
+    }
+
+    /// Update role ix is used by the authority of the Role to replace its permissions and
+    /// allowed programs, which applies to every delegation holding the Role at once.
+    pub fn update_role(
+        ctx: Context<UpdateRole>,
+        permissions: u64,
+        allowed_programs: Vec<Pubkey>,
+    ) -> Result<()> {
+        ctx.accounts.role.set_terms(permissions, allowed_programs)
+    }
+
+    /// Close role ix is used by the authority of the Role to remove it. Delegations still
+    /// holding the Role are no longer honoured until their master unassigns it.
+    pub fn close_role(_ctx: Context<CloseRole>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Assign role ix is used by the master to let the permissions and allowed programs of one
+    /// of its Roles apply to the delegation instead of its own. The delegation is then only
+    /// honoured when the Role is passed along, e.g. with check_authorization_with_role.
+    /// Sub-delegations can't hold a Role, nor can delegations holding one be re-delegated.
+    /// Roles of another authority, e.g. a protocol defining roles for its users, are only
+    /// assigned with protocol_role set, by which the master consents to the authority widening
+    /// the delegation at any time.
+    pub fn assign_role(ctx: Context<AssignRole>, protocol_role: bool) -> Result<()> {
+        let delegation = &mut ctx.accounts.delegation;
+        require!(
+            delegation.parent.is_none(),
+            DelegationError::RoleSubDelegation
+        );
+        // a Role stays under the control of its authority, which could widen it at any time
+        require!(
+            protocol_role || ctx.accounts.role.authority == delegation.master,
+            DelegationError::WrongRoleAuthority
+        );
+        delegation.role = Some(ctx.accounts.role.key());
+        Ok(())
+    }
+
+    /// Unassign role ix is used by the master to let the delegation's own permissions and
+    /// allowed programs apply again.
+    pub fn unassign_role(ctx: Context<UnassignRole>) -> Result<()> {
+        ctx.accounts.delegation.role = None;
+        Ok(())
+    }
+
+    /// Set allowance ix is used by the master to cap the amount of a mint the representative
+    /// may spend on the master's behalf. Use LAMPORTS_MINT to cap native SOL spending.
+    /// Updating an existing Allowance changes the cap, but keeps the amount spent so far,
+    /// unless it was set for an earlier Delegation at the same address.
+    pub fn set_allowance(ctx: Context<SetAllowance>, mint: Pubkey, cap: u64) -> Result<()> {
+        let delegation = &ctx.accounts.delegation;
+        let allowance = &mut ctx.accounts.allowance;
+        if allowance.delegation_created_slot != delegation.created_slot {
+            allowance.spent = 0;
+        }
+        allowance.delegation = delegation.key();
+        allowance.delegation_created_slot = delegation.created_slot;
+        allowance.master = ctx.accounts.master.key();
+        allowance.mint = mint;
+        allowance.cap = cap;
+        Ok(())
+    }
+
+    /// Consume allowance ix is invoked by integrating programs, signed by the representative,
+    /// before moving the master's funds. It fails if the amount would exceed the cap, or if the
+    /// Allowance was set for an earlier Delegation at the same address. Sub-delegations can't
+    /// spend allowances, since their parent isn't checked here.
+    pub fn consume_allowance(ctx: Context<ConsumeAllowance>, amount: u64) -> Result<()> {
+        let delegation = &ctx.accounts.delegation;
+        require!(delegation.parent.is_none(), DelegationError::SubDelegation);
+        require!(
+            ctx.accounts.representative.key() == delegation.representative,
+            DelegationError::WrongRepresentative
+        );
+        require!(
+            ctx.accounts.allowance.delegation_created_slot == delegation.created_slot,
+            DelegationError::StaleAllowance
+        );
+        require!(delegation.authorised, DelegationError::NotAuthorized);
+        delegation.check_validity(Clock::get()?.unix_timestamp)?;
+        check_master_settings(
+            &ctx.accounts.master_settings,
+            &delegation.master,
+            delegation,
+        )?;
+        ctx.accounts.allowance.consume(amount)
+    }
+
+    /// Close allowance ix is used by the master to remove the Allowance account,
+    /// which is also required to reset the amount spent.
+    pub fn close_allowance(_ctx: Context<CloseAllowance>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Pause all ix is used by the master to suspend every one of its delegations at once,
+    /// e.g. while investigating a compromised representative. Delegations are honoured again
+    /// once the master invokes resume all.
+    pub fn pause_all(ctx: Context<UpdateMasterSettings>) -> Result<()> {
+        let master_settings = &mut ctx.accounts.master_settings;
+        master_settings.master = ctx.accounts.master.key();
+        master_settings.paused = true;
+        Ok(())
+    }
+
+    /// Resume all ix is used by the master to lift the suspension set by pause all.
+    pub fn resume_all(ctx: Context<UpdateMasterSettings>) -> Result<()> {
+        let master_settings = &mut ctx.accounts.master_settings;
+        master_settings.master = ctx.accounts.master.key();
+        master_settings.paused = false;
+        Ok(())
+    }
+
+    /// Revoke all ix is used by the master to permanently invalidate every delegation it
+    /// created so far, without cancelling them one by one, by starting a new revocation epoch.
+    /// Delegations created afterwards, even in the same slot, are not affected.
+    pub fn revoke_all(ctx: Context<UpdateMasterSettings>) -> Result<()> {
+        let master_settings = &mut ctx.accounts.master_settings;
+        master_settings.master = ctx.accounts.master.key();
+        master_settings.revocation_epoch += 1;
+        Ok(())
+    }
+
+    /// Propose master transfer ix is used by the master to name the wallet, e.g. a hardware
+    /// wallet or a multisig, its delegations should be transferred to. Proposing again
+    /// replaces the previously proposed wallet.
+    pub fn propose_master_transfer(
+        ctx: Context<ProposeMasterTransfer>,
+        new_master: Pubkey,
+    ) -> Result<()> {
+        require_keys_neq!(
+            new_master,
+            ctx.accounts.master.key(),
+            DelegationError::SameMaster
+        );
+        let master_transfer = &mut ctx.accounts.master_transfer;
+        master_transfer.master = ctx.accounts.master.key();
+        master_transfer.new_master = new_master;
+        Ok(())
+    }
+
+    /// Accept master transfer ix is used by the proposed wallet to become the master of the
+    /// Delegations passed as remaining accounts, each followed by the RepresentativeIndex of
+    /// its representative. The Delegations keep their address, terms and confirmation, so
+    /// representatives don't need to confirm them again. It can be invoked several times to
+    /// transfer delegations in batches, while the proposal is open. Paused or revoked
+    /// delegations, sub-delegations and legacy delegations can't be transferred. Roles are
+    /// unassigned, since the new master didn't consent to the authority of the Role.
+    pub fn accept_master_transfer<'a, 'b, 'c, 'info>(
+        ctx: Context<'a, 'b, 'c, 'info, AcceptMasterTransfer<'info>>,
+    ) -> Result<()> {
+        let master = ctx.accounts.master.key();
+        let new_master = ctx.accounts.new_master.key();
+        let epoch = revocation_epoch(&ctx.accounts.new_master_settings)?;
+        let remaining_accounts = ctx.remaining_accounts.chunks_exact(2);
+        require!(
+            remaining_accounts.remainder().is_empty(),
+            DelegationError::InvalidRemainingAccounts
+        );
+
+        for accounts in remaining_accounts {
+            let (delegation_info, representative_index) = (&accounts[0], &accounts[1]);
+            let mut delegation = Account::<Delegation>::try_from(delegation_info)?;
+            require_keys_eq!(delegation.master, master, DelegationError::WrongMaster);
+            require!(delegation.parent.is_none(), DelegationError::SubDelegation);
+            check_master_settings(&ctx.accounts.master_settings, &master, &delegation)?;
+            require_keys_eq!(
+                representative_index.key(),
+                get_representative_index_address(&delegation.representative),
+                ErrorCode::ConstraintSeeds
+            );
+
+            if delegation.original_master.is_none() {
+                delegation.original_master = Some(master);
+            }
+            delegation.master = new_master;
+            delegation.revocation_epoch = epoch;
+            delegation.role = None;
+            delegation.exit(&ID)?;
+
+            remove_from_master_index(&ctx.accounts.master_index, &delegation.key())?;
+            add_to_master_index(
+                &ctx.accounts.new_master_index,
+                *ctx.bumps.get("new_master_index").unwrap(),
+                &new_master,
+                &delegation.representative,
+                &delegation.key(),
+                &ctx.accounts.new_master,
+                &ctx.accounts.system_program,
+            )?;
+            transfer_in_representative_index(representative_index, &delegation.key(), &new_master)?;
+
+            emit!(MasterTransferred {
+                master,
+                new_master,
+                representative: delegation.representative,
+                delegation: delegation.key(),
+                slot: Clock::get()?.slot,
+            });
+        }
+        Ok(())
+    }
 
+    /// Close master transfer ix is used by the master to withdraw its proposal, or to reclaim
+    /// the rent of the MasterTransfer account once every Delegation was transferred.
+    pub fn close_master_transfer(_ctx: Context<CloseMasterTransfer>) -> Result<()> {
         Ok(())
     }
 }
@@ -73,86 +1142,2071 @@ pub mod delegation_manager {
 /// Accounts passed to InitializeDelegation instruction
 #[derive(Accounts)]
 pub struct InitializeDelegation<'info> {
-    #[account(mut)]
     /// The one invoking the instruction to create Delegation
     pub master: Signer<'info>,
+    #[account(mut)]
+    /// The one paying the rent, refunded when the Delegation is closed
+    pub payer: Signer<'info>,
     ///CHECK: can be any account which can sign confirmation
     pub representative: UncheckedAccount<'info>,
     #[account(
         init,
         seeds = [AUTHORIZE_SEED, master.key().as_ref(), representative.key().as_ref()],
         bump,
-        space = 8 + 32 + 32 + 1,
-        payer = master
+        space = Delegation::SPACE,
+        payer = payer
     )]
     /// The Delegation PDA account derived from the master and representativ pubkeys
     pub delegation: Box<Account<'info, Delegation>>,
+    #[account(mut, seeds = [MASTER_INDEX_SEED, master.key().as_ref()], bump)]
+    ///CHECK: MasterIndex of the master, created along with its first Delegation
+    pub master_index: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [REPRESENTATIVE_INDEX_SEED, representative.key().as_ref()],
+        bump
+    )]
+    ///CHECK: RepresentativeIndex of the representative, created along with its first Delegation
+    pub representative_index: UncheckedAccount<'info>,
+    #[account(seeds = [MASTER_SETTINGS_SEED, master.key().as_ref()], bump)]
+    ///CHECK: MasterSettings of the master, which may not exist, read for its revocation epoch
+    pub master_settings: UncheckedAccount<'info>,
     pub system_program: Program<'info, System>,
 }
 
-/// Accounts passed to ConfirmDelegation instruction
+/// Accounts passed to InitializeDelegationsBatch instruction
 #[derive(Accounts)]
-pub struct ConfirmDelegation<'info> {
-    #[account(mut)]
-    pub representative: Signer<'info>,
+pub struct InitializeDelegationsBatch<'info> {
+    /// The one invoking the instruction to create the Delegations
+    pub master: Signer<'info>,
     #[account(mut)]
-    pub delegation: Box<Account<'info, Delegation>>,
+    /// The one paying the rent, refunded when the Delegations are closed
+    pub payer: Signer<'info>,
+    #[account(mut, seeds = [MASTER_INDEX_SEED, master.key().as_ref()], bump)]
+    ///CHECK: MasterIndex of the master, created along with its first Delegation
+    pub master_index: UncheckedAccount<'info>,
+    #[account(seeds = [MASTER_SETTINGS_SEED, master.key().as_ref()], bump)]
+    ///CHECK: MasterSettings of the master, which may not exist, read for its revocation epoch
+    pub master_settings: UncheckedAccount<'info>,
     pub system_program: Program<'info, System>,
 }
 
-/// Accounts passed to CancelDelegation instruction
+/// Accounts passed to InitializeSessionDelegation instruction
 #[derive(Accounts)]
-pub struct CancelDelegation<'info> {
+pub struct InitializeSessionDelegation<'info> {
     #[account(mut)]
+    /// The one invoking the instruction and paying the rent, refunded when the session closes
+    pub master: Signer<'info>,
+    ///CHECK: ephemeral key generated by the master
+    pub session_key: UncheckedAccount<'info>,
+    #[account(
+        init,
+        seeds = [AUTHORIZE_SEED, master.key().as_ref(), session_key.key().as_ref()],
+        bump,
+        space = Delegation::SPACE,
+        payer = master
+    )]
+    /// The Delegation PDA account derived from the master and session key pubkeys
     pub delegation: Box<Account<'info, Delegation>>,
+    #[account(mut, seeds = [MASTER_INDEX_SEED, master.key().as_ref()], bump)]
+    ///CHECK: MasterIndex of the master, created along with its first Delegation
+    pub master_index: UncheckedAccount<'info>,
+    #[account(seeds = [MASTER_SETTINGS_SEED, master.key().as_ref()], bump)]
+    ///CHECK: MasterSettings of the master, which may not exist, read for its revocation epoch
+    pub master_settings: UncheckedAccount<'info>,
     pub system_program: Program<'info, System>,
 }
 
-/// State account storing the delegation
-#[account]
-#[derive(Debug)]
-pub struct Delegation {
-    /// The creator of the delegation
-    pub master: Pubkey,
-    /// The wallet who delegates
-    pub representative: Pubkey,
-    /// Confirmation flag
-    pub authorised: bool,
+/// Accounts passed to CloseExpiredSession instruction
+#[derive(Accounts)]
+pub struct CloseExpiredSession<'info> {
+    ///CHECK: checked by has_one
+    pub master: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        has_one = master @ DelegationError::WrongMaster,
+        close = rent_recipient
+    )]
+    pub delegation: Box<Account<'info, Delegation>>,
+    #[account(mut)]
+    ///CHECK: receives the rent of the closed session, checked by the instruction
+    pub rent_recipient: UncheckedAccount<'info>,
+    #[account(mut, seeds = [MASTER_INDEX_SEED, master.key().as_ref()], bump)]
+    ///CHECK: MasterIndex of the master
+    pub master_index: UncheckedAccount<'info>,
 }
 
-/// Program errors
-#[error_code]
-pub enum DelegationError {
-    #[msg("Wrong representative!")]
-    WrongRepresentative,
-    #[msg("Wrong authority!")]
-    WrongMaster,
-    #[msg("Wrong signer!")]
-    WrongSigner,
-    #[msg("Authorization already approved!")]
-    AlreadyAuthorised,
-    #[msg("The account provided has no authority!")]
-    NotAuthorized,
-}
+/// Accounts passed to InitializeNamespacedDelegation instruction
+#[derive(Accounts)]
+#[instruction(namespace: Pubkey)]
+pub struct InitializeNamespacedDelegation<'info> {
+    /// The one invoking the instruction to create Delegation
+    pub master: Signer<'info>,
+    #[account(mut)]
+    /// The one paying the rent, refunded when the Delegation is closed
+    pub payer: Signer<'info>,
+    ///CHECK: can be any account which can sign confirmation
+    pub representative: UncheckedAccount<'info>,
+    #[account(
+        init,
+        seeds = [
+            AUTHORIZE_SEED,
+            master.key().as_ref(),
+            representative.key().as_ref(),
+            namespace.as_ref()
+        ],
+        bump,
+        space = Delegation::SPACE,
+        payer = payer
+    )]
+    /// The Delegation PDA account derived from the master, representative and namespace pubkeys
+    pub delegation: Box<Account<'info, Delegation>>,
+    #[account(mut, seeds = [MASTER_INDEX_SEED, master.key().as_ref()], bump)]
+    ///CHECK: MasterIndex of the master, created along with its first Delegation
+    pub master_index: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [REPRESENTATIVE_INDEX_SEED, representative.key().as_ref()],
+        bump
+    )]
+    ///CHECK: RepresentativeIndex of the representative, created along with its first Delegation
+    pub representative_index: UncheckedAccount<'info>,
+    #[account(seeds = [MASTER_SETTINGS_SEED, master.key().as_ref()], bump)]
+    ///CHECK: MasterSettings of the master, which may not exist, read for its revocation epoch
+    pub master_settings: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Accounts passed to InitializeSubDelegation instruction
+#[derive(Accounts)]
+pub struct InitializeSubDelegation<'info> {
+    /// The representative of the parent Delegation re-delegating its authority
+    pub delegator: Signer<'info>,
+    #[account(mut)]
+    /// The one paying the rent, refunded when the Delegation is closed
+    pub payer: Signer<'info>,
+    pub parent_delegation: Box<Account<'info, Delegation>>,
+    ///CHECK: can be any account which can sign confirmation
+    pub representative: UncheckedAccount<'info>,
+    #[account(
+        init,
+        seeds = [
+            AUTHORIZE_SEED,
+            parent_delegation.key().as_ref(),
+            representative.key().as_ref()
+        ],
+        bump,
+        space = Delegation::SPACE,
+        payer = payer
+    )]
+    /// The child Delegation PDA account derived from the parent Delegation and representative
+    pub delegation: Box<Account<'info, Delegation>>,
+    #[account(mut, seeds = [MASTER_INDEX_SEED, delegator.key().as_ref()], bump)]
+    ///CHECK: MasterIndex of the delegator, created along with its first Delegation
+    pub master_index: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [REPRESENTATIVE_INDEX_SEED, representative.key().as_ref()],
+        bump
+    )]
+    ///CHECK: RepresentativeIndex of the representative, created along with its first Delegation
+    pub representative_index: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Accounts passed to UseDelegation instruction
+#[derive(Accounts)]
+pub struct UseDelegation<'info> {
+    #[account(mut)]
+    ///CHECK: checked against the Delegation account
+    pub master: UncheckedAccount<'info>,
+    pub representative: Signer<'info>,
+    #[account(mut)]
+    pub delegation: Box<Account<'info, Delegation>>,
+    ///CHECK: MasterSettings of the master, checked by check_master_settings
+    pub master_settings: UncheckedAccount<'info>,
+    #[account(mut, seeds = [MASTER_INDEX_SEED, delegation.master.as_ref()], bump)]
+    ///CHECK: MasterIndex of the master, updated once the Delegation is used up
+    pub master_index: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [REPRESENTATIVE_INDEX_SEED, delegation.representative.as_ref()],
+        bump
+    )]
+    ///CHECK: RepresentativeIndex of the representative, updated once the Delegation is used up
+    pub representative_index: UncheckedAccount<'info>,
+}
+
+/// Accounts passed to AssertAuthorized instruction
+#[derive(Accounts)]
+pub struct AssertAuthorized<'info> {
+    ///CHECK: checked against the Delegation account
+    pub master: UncheckedAccount<'info>,
+    ///CHECK: checked against the Delegation account
+    pub representative: UncheckedAccount<'info>,
+    ///CHECK: Delegation in any layout, checked by load_authorized_delegation
+    pub delegation: UncheckedAccount<'info>,
+    ///CHECK: MasterSettings of the master, checked by check_master_settings
+    pub master_settings: UncheckedAccount<'info>,
+}
+
+/// Accounts passed to AssertAuthorizedChain instruction
+#[derive(Accounts)]
+pub struct AssertAuthorizedChain<'info> {
+    ///CHECK: checked against the first Delegation of the chain
+    pub master: UncheckedAccount<'info>,
+    ///CHECK: checked against the last Delegation of the chain
+    pub representative: UncheckedAccount<'info>,
+    ///CHECK: MasterSettings of the master, checked by check_master_settings
+    pub master_settings: UncheckedAccount<'info>,
+}
+
+/// Accounts passed to SetAllowedPrograms instruction
+#[derive(Accounts)]
+pub struct SetAllowedPrograms<'info> {
+    pub master: Signer<'info>,
+    #[account(mut, has_one = master @ DelegationError::WrongMaster)]
+    pub delegation: Box<Account<'info, Delegation>>,
+}
+
+/// Accounts passed to CreateRole instruction
+#[derive(Accounts)]
+#[instruction(name: String)]
+pub struct CreateRole<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(
+        init,
+        seeds = [ROLE_SEED, authority.key().as_ref(), hash(name.as_bytes()).as_ref()],
+        bump,
+        space = Role::SPACE,
+        payer = authority
+    )]
+    /// The Role PDA account derived from the authority and the hash of the name of the Role,
+    /// which unlike the name itself always fits in a seed
+    pub role: Box<Account<'info, Role>>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Accounts passed to UpdateRole instruction
+#[derive(Accounts)]
+pub struct UpdateRole<'info> {
+    pub authority: Signer<'info>,
+    #[account(mut, has_one = authority @ DelegationError::WrongRoleAuthority)]
+    pub role: Box<Account<'info, Role>>,
+}
+
+/// Accounts passed to CloseRole instruction
+#[derive(Accounts)]
+pub struct CloseRole<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        has_one = authority @ DelegationError::WrongRoleAuthority,
+        close = authority
+    )]
+    pub role: Box<Account<'info, Role>>,
+}
+
+/// Accounts passed to AssignRole instruction
+#[derive(Accounts)]
+pub struct AssignRole<'info> {
+    pub master: Signer<'info>,
+    #[account(mut, has_one = master @ DelegationError::WrongMaster)]
+    pub delegation: Box<Account<'info, Delegation>>,
+    pub role: Box<Account<'info, Role>>,
+}
+
+/// Accounts passed to UnassignRole instruction
+#[derive(Accounts)]
+pub struct UnassignRole<'info> {
+    pub master: Signer<'info>,
+    #[account(mut, has_one = master @ DelegationError::WrongMaster)]
+    pub delegation: Box<Account<'info, Delegation>>,
+}
+
+/// Accounts passed to UpdateDelegationMetadata instruction
+#[derive(Accounts)]
+pub struct UpdateDelegationMetadata<'info> {
+    pub master: Signer<'info>,
+    #[account(mut, has_one = master @ DelegationError::WrongMaster)]
+    pub delegation: Box<Account<'info, Delegation>>,
+}
+
+/// Accounts passed to SetRotationAllowed instruction
+#[derive(Accounts)]
+pub struct SetRotationAllowed<'info> {
+    pub master: Signer<'info>,
+    #[account(mut, has_one = master @ DelegationError::WrongMaster)]
+    pub delegation: Box<Account<'info, Delegation>>,
+}
+
+/// Accounts passed to RequestDelegation instruction
+#[derive(Accounts)]
+pub struct RequestDelegation<'info> {
+    #[account(mut)]
+    /// The one invoking the instruction to request the Delegation
+    pub representative: Signer<'info>,
+    ///CHECK: can be any account which can sign the approval
+    pub master: UncheckedAccount<'info>,
+    #[account(
+        init,
+        seeds = [AUTHORIZE_SEED, master.key().as_ref(), representative.key().as_ref()],
+        bump,
+        space = Delegation::SPACE,
+        payer = representative
+    )]
+    /// The Delegation PDA account derived from the master and representative pubkeys
+    pub delegation: Box<Account<'info, Delegation>>,
+    #[account(
+        mut,
+        seeds = [REPRESENTATIVE_INDEX_SEED, representative.key().as_ref()],
+        bump
+    )]
+    ///CHECK: RepresentativeIndex of the representative, created along with its first Delegation
+    pub representative_index: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Accounts passed to ApproveDelegationRequest instruction
+#[derive(Accounts)]
+pub struct ApproveDelegationRequest<'info> {
+    #[account(mut)]
+    pub master: Signer<'info>,
+    #[account(mut, has_one = master @ DelegationError::WrongMaster)]
+    pub delegation: Box<Account<'info, Delegation>>,
+    #[account(mut, seeds = [MASTER_INDEX_SEED, master.key().as_ref()], bump)]
+    ///CHECK: MasterIndex of the master, created along with its first Delegation
+    pub master_index: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [REPRESENTATIVE_INDEX_SEED, delegation.representative.as_ref()],
+        bump
+    )]
+    ///CHECK: RepresentativeIndex of the representative
+    pub representative_index: UncheckedAccount<'info>,
+    #[account(seeds = [MASTER_SETTINGS_SEED, master.key().as_ref()], bump)]
+    ///CHECK: MasterSettings of the master, which may not exist, read for its revocation epoch
+    pub master_settings: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Accounts passed to ConfirmDelegation instruction
+#[derive(Accounts)]
+pub struct ConfirmDelegation<'info> {
+    #[account(mut)]
+    pub representative: Signer<'info>,
+    #[account(mut)]
+    pub delegation: Box<Account<'info, Delegation>>,
+    #[account(
+        mut,
+        seeds = [REPRESENTATIVE_INDEX_SEED, representative.key().as_ref()],
+        bump
+    )]
+    ///CHECK: RepresentativeIndex of the representative, which doesn't exist for delegations
+    /// created before it
+    pub representative_index: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Accounts passed to ConfirmDelegationWithSignature instruction
+#[derive(Accounts)]
+pub struct ConfirmDelegationWithSignature<'info> {
+    ///CHECK: checked by has_one, signs off-chain
+    pub representative: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        has_one = representative @ DelegationError::WrongRepresentative
+    )]
+    pub delegation: Box<Account<'info, Delegation>>,
+    #[account(
+        mut,
+        seeds = [REPRESENTATIVE_INDEX_SEED, representative.key().as_ref()],
+        bump
+    )]
+    ///CHECK: RepresentativeIndex of the representative, which doesn't exist for delegations
+    /// created before it
+    pub representative_index: UncheckedAccount<'info>,
+    #[account(address = anchor_lang::solana_program::sysvar::instructions::ID)]
+    ///CHECK: Instructions sysvar, checked by address
+    pub instructions: UncheckedAccount<'info>,
+}
+
+/// Accounts passed to DeclineDelegation instruction
+#[derive(Accounts)]
+pub struct DeclineDelegation<'info> {
+    pub representative: Signer<'info>,
+    ///CHECK: checked by has_one
+    pub master: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        has_one = master @ DelegationError::WrongMaster,
+        has_one = representative @ DelegationError::WrongRepresentative,
+        close = rent_recipient
+    )]
+    pub delegation: Box<Account<'info, Delegation>>,
+    #[account(
+        mut,
+        address = delegation.refund_recipient() @ DelegationError::WrongRentRecipient
+    )]
+    ///CHECK: receives the rent of the declined Delegation
+    pub rent_recipient: UncheckedAccount<'info>,
+    #[account(mut, seeds = [MASTER_INDEX_SEED, master.key().as_ref()], bump)]
+    ///CHECK: MasterIndex of the master, which doesn't exist for delegations created before it
+    pub master_index: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [REPRESENTATIVE_INDEX_SEED, representative.key().as_ref()],
+        bump
+    )]
+    ///CHECK: RepresentativeIndex of the representative, which doesn't exist for delegations
+    /// created before it
+    pub representative_index: UncheckedAccount<'info>,
+}
+
+/// Accounts passed to RotateRepresentative instruction
+#[derive(Accounts)]
+pub struct RotateRepresentative<'info> {
+    #[account(mut)]
+    /// The current representative, paying for the new Delegation
+    pub representative: Signer<'info>,
+    /// The key the representative rotates to
+    pub new_representative: Signer<'info>,
+    ///CHECK: checked by has_one, needs to sign unless the master allowed rotations
+    pub master: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        has_one = master @ DelegationError::WrongMaster,
+        has_one = representative @ DelegationError::WrongRepresentative,
+        close = representative
+    )]
+    pub delegation: Box<Account<'info, Delegation>>,
+    #[account(mut)]
+    ///CHECK: the new Delegation PDA, derived like the current one by the instruction
+    pub new_delegation: UncheckedAccount<'info>,
+    #[account(mut, seeds = [MASTER_INDEX_SEED, master.key().as_ref()], bump)]
+    ///CHECK: MasterIndex of the master, which doesn't exist for delegations created before it
+    pub master_index: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [REPRESENTATIVE_INDEX_SEED, representative.key().as_ref()],
+        bump
+    )]
+    ///CHECK: RepresentativeIndex of the representative, which doesn't exist for delegations
+    /// created before it
+    pub representative_index: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [REPRESENTATIVE_INDEX_SEED, new_representative.key().as_ref()],
+        bump
+    )]
+    ///CHECK: RepresentativeIndex of the new representative, created along with its first
+    /// Delegation
+    pub new_representative_index: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Accounts passed to MigrateDelegation instruction
+#[derive(Accounts)]
+pub struct MigrateDelegation<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(mut, owner = ID)]
+    ///CHECK: Delegation in the legacy layout, which can't be deserialized as an Account
+    pub delegation: UncheckedAccount<'info>,
+    #[account(mut)]
+    ///CHECK: MasterIndex of the master, derived from the legacy Delegation by the instruction
+    pub master_index: UncheckedAccount<'info>,
+    #[account(mut)]
+    ///CHECK: RepresentativeIndex of the representative, derived from the legacy Delegation by
+    /// the instruction
+    pub representative_index: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Accounts passed to CancelDelegation instruction
+#[derive(Accounts)]
+pub struct CancelDelegation<'info> {
+    #[account(mut)]
+    ///CHECK: Delegation in any layout, so legacy ones can be cancelled without being migrated,
+    /// loaded by the instruction
+    pub delegation: UncheckedAccount<'info>,
+    #[account(mut)]
+    ///CHECK: receives the rent of the cancelled Delegation, checked by the instruction
+    pub rent_recipient: UncheckedAccount<'info>,
+    #[account(mut)]
+    ///CHECK: MasterIndex of the master, which doesn't exist for delegations created before it
+    pub master_index: UncheckedAccount<'info>,
+    #[account(mut)]
+    ///CHECK: RepresentativeIndex of the representative, which doesn't exist for delegations
+    /// created before it
+    pub representative_index: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Accounts passed to CancelDelegationsBatch instruction
+#[derive(Accounts)]
+pub struct CancelDelegationsBatch<'info> {
+    /// The master or representative of every cancelled Delegation
+    pub signer: Signer<'info>,
+    #[account(mut, seeds = [MASTER_INDEX_SEED, signer.key().as_ref()], bump)]
+    ///CHECK: MasterIndex of the signer, which may not exist
+    pub master_index: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [REPRESENTATIVE_INDEX_SEED, signer.key().as_ref()],
+        bump
+    )]
+    ///CHECK: RepresentativeIndex of the signer, which may not exist
+    pub representative_index: UncheckedAccount<'info>,
+}
+
+/// Accounts passed to SetAllowance instruction
+#[derive(Accounts)]
+#[instruction(mint: Pubkey)]
+pub struct SetAllowance<'info> {
+    #[account(mut)]
+    pub master: Signer<'info>,
+    #[account(has_one = master @ DelegationError::WrongMaster)]
+    pub delegation: Box<Account<'info, Delegation>>,
+    #[account(
+        init_if_needed,
+        seeds = [ALLOWANCE_SEED, delegation.key().as_ref(), mint.as_ref()],
+        bump,
+        space = Allowance::SPACE,
+        payer = master
+    )]
+    /// The Allowance PDA account derived from the delegation and mint pubkeys
+    pub allowance: Box<Account<'info, Allowance>>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Accounts passed to ConsumeAllowance instruction
+#[derive(Accounts)]
+pub struct ConsumeAllowance<'info> {
+    pub representative: Signer<'info>,
+    pub delegation: Box<Account<'info, Delegation>>,
+    #[account(
+        mut,
+        seeds = [ALLOWANCE_SEED, delegation.key().as_ref(), allowance.mint.as_ref()],
+        bump,
+        has_one = delegation
+    )]
+    pub allowance: Box<Account<'info, Allowance>>,
+    ///CHECK: MasterSettings of the master, checked by check_master_settings
+    pub master_settings: UncheckedAccount<'info>,
+}
+
+/// Accounts passed to PauseAll, ResumeAll and RevokeAll instructions
+#[derive(Accounts)]
+pub struct UpdateMasterSettings<'info> {
+    #[account(mut)]
+    pub master: Signer<'info>,
+    #[account(
+        init_if_needed,
+        seeds = [MASTER_SETTINGS_SEED, master.key().as_ref()],
+        bump,
+        space = MasterSettings::SPACE,
+        payer = master
+    )]
+    /// The MasterSettings PDA account derived from the master pubkey
+    pub master_settings: Box<Account<'info, MasterSettings>>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Accounts passed to ProposeMasterTransfer instruction
+#[derive(Accounts)]
+pub struct ProposeMasterTransfer<'info> {
+    #[account(mut)]
+    pub master: Signer<'info>,
+    #[account(
+        init_if_needed,
+        seeds = [MASTER_TRANSFER_SEED, master.key().as_ref()],
+        bump,
+        space = MasterTransfer::SPACE,
+        payer = master
+    )]
+    /// The MasterTransfer PDA account derived from the master pubkey
+    pub master_transfer: Box<Account<'info, MasterTransfer>>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Accounts passed to AcceptMasterTransfer instruction
+#[derive(Accounts)]
+pub struct AcceptMasterTransfer<'info> {
+    #[account(mut)]
+    /// The wallet proposed by the master, paying for the growth of its MasterIndex
+    pub new_master: Signer<'info>,
+    ///CHECK: checked against the MasterTransfer account
+    pub master: UncheckedAccount<'info>,
+    #[account(
+        seeds = [MASTER_TRANSFER_SEED, master.key().as_ref()],
+        bump,
+        has_one = master @ DelegationError::WrongMaster,
+        has_one = new_master @ DelegationError::WrongMaster
+    )]
+    pub master_transfer: Box<Account<'info, MasterTransfer>>,
+    ///CHECK: MasterSettings of the master, checked by check_master_settings
+    pub master_settings: UncheckedAccount<'info>,
+    #[account(seeds = [MASTER_SETTINGS_SEED, new_master.key().as_ref()], bump)]
+    ///CHECK: MasterSettings of the new master, which may not exist, read for its revocation epoch
+    pub new_master_settings: UncheckedAccount<'info>,
+    #[account(mut, seeds = [MASTER_INDEX_SEED, master.key().as_ref()], bump)]
+    ///CHECK: MasterIndex of the master, which doesn't exist for delegations created before it
+    pub master_index: UncheckedAccount<'info>,
+    #[account(mut, seeds = [MASTER_INDEX_SEED, new_master.key().as_ref()], bump)]
+    ///CHECK: MasterIndex of the new master, created along with its first Delegation
+    pub new_master_index: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Accounts passed to CloseMasterTransfer instruction
+#[derive(Accounts)]
+pub struct CloseMasterTransfer<'info> {
+    #[account(mut)]
+    pub master: Signer<'info>,
+    #[account(
+        mut,
+        has_one = master @ DelegationError::WrongMaster,
+        close = master
+    )]
+    pub master_transfer: Box<Account<'info, MasterTransfer>>,
+}
+
+/// Accounts passed to CloseAllowance instruction
+#[derive(Accounts)]
+pub struct CloseAllowance<'info> {
+    #[account(mut)]
+    pub master: Signer<'info>,
+    #[account(
+        mut,
+        has_one = master @ DelegationError::WrongMaster,
+        close = master
+    )]
+    pub allowance: Box<Account<'info, Allowance>>,
+}
+
+/// State account storing the delegation
+#[account]
+#[derive(Debug)]
+pub struct Delegation {
+    /// Layout version of the account, DELEGATION_VERSION for accounts created by this program
+    pub version: u8,
+    /// The creator of the delegation
+    pub master: Pubkey,
+    /// The wallet who delegates
+    pub representative: Pubkey,
+    /// Confirmation flag
+    pub authorised: bool,
+    /// Bitmask of the scopes the representative may act in
+    pub permissions: u64,
+    /// Unix timestamp before which the delegation is not honoured
+    pub valid_from: Option<i64>,
+    /// Unix timestamp after which the delegation is not honoured
+    pub valid_until: Option<i64>,
+    /// Programs which honour the delegation, any program if empty
+    pub allowed_programs: Vec<Pubkey>,
+    /// Protocol namespace the delegation is derived from, if any
+    pub namespace: Option<Pubkey>,
+    /// Number of times the delegation may be used, unlimited if None
+    pub max_uses: Option<u32>,
+    /// Number of times the delegation was used
+    pub uses: u32,
+    /// The Delegation this one was re-delegated from, if any
+    pub parent: Option<Pubkey>,
+    /// The slot in which the parent Delegation was created
+    pub parent_created_slot: u64,
+    /// Number of Delegations between this one and the original master
+    pub depth: u8,
+    /// The slot in which the Delegation was created, or migrated for legacy ones
+    pub created_slot: u64,
+    /// Set if the representative requested the delegation and paid for the account
+    pub requested: bool,
+    /// The master the Delegation address was derived from, if the master role was transferred
+    pub original_master: Option<Pubkey>,
+    /// Set if the representative may rotate to a new key without the master signing
+    pub rotation_allowed: bool,
+    /// The one who paid the rent and is refunded when the Delegation is closed. Delegations
+    /// created before it refund the representative if requested and the master otherwise.
+    pub rent_recipient: Option<Pubkey>,
+    /// Human-readable label set by the master, at most MAX_LABEL_LEN bytes
+    pub label: String,
+    /// URI of off-chain metadata describing the delegation, at most MAX_METADATA_URI_LEN bytes
+    pub metadata_uri: String,
+    /// Set for session key delegations, which anyone can close once expired
+    pub session: bool,
+    /// Role whose permissions and allowed programs apply instead of the Delegation's own, if any
+    pub role: Option<Pubkey>,
+    /// Revocation epoch of the master when the Delegation was authorised, revoked once
+    /// revoke_all starts a later one
+    pub revocation_epoch: u64,
+    /// Zeroed space, out of which fields added later are carved without changing SPACE
+    pub reserved: [u64; 7],
+}
+
+/// Layout of the Delegation accounts created before the layout was versioned
+#[derive(AnchorDeserialize)]
+struct LegacyDelegation {
+    master: Pubkey,
+    representative: Pubkey,
+    authorised: bool,
+}
+
+/// Description of a Delegation, set by the master to tell its delegations apart
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, Default)]
+pub struct DelegationMetadata {
+    /// Human-readable label, at most MAX_LABEL_LEN bytes, empty if none
+    pub label: String,
+    /// URI of off-chain metadata, at most MAX_METADATA_URI_LEN bytes, empty if none
+    pub metadata_uri: String,
+}
+
+impl Default for Delegation {
+    /// Returns the zeroed Delegation anchor's init starts from
+    fn default() -> Self {
+        Self {
+            version: 0,
+            master: Pubkey::default(),
+            representative: Pubkey::default(),
+            authorised: false,
+            permissions: 0,
+            valid_from: None,
+            valid_until: None,
+            allowed_programs: vec![],
+            namespace: None,
+            max_uses: None,
+            uses: 0,
+            parent: None,
+            parent_created_slot: 0,
+            depth: 0,
+            created_slot: 0,
+            requested: false,
+            original_master: None,
+            rotation_allowed: false,
+            rent_recipient: None,
+            label: String::new(),
+            metadata_uri: String::new(),
+            session: false,
+            role: None,
+            revocation_epoch: 0,
+            reserved: [0; 7],
+        }
+    }
+}
+
+/// Terms under which the representative may act, set by the master when creating a Delegation
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct DelegationTerms {
+    /// Bitmask of the scopes the representative may act in, PERMISSION_ALL grants every scope
+    pub permissions: u64,
+    /// Unix timestamp before which the delegation is not honoured
+    pub valid_from: Option<i64>,
+    /// Unix timestamp after which the delegation is not honoured
+    pub valid_until: Option<i64>,
+    /// Programs which honour the delegation, any program if empty
+    pub allowed_programs: Vec<Pubkey>,
+    /// Number of times the delegation may be used, unlimited if None
+    pub max_uses: Option<u32>,
+}
+
+impl Delegation {
+    pub const SPACE: usize = 8
+        + 1
+        + 32
+        + 32
+        + 1
+        + 8
+        + (1 + 8)
+        + (1 + 8)
+        + (4 + 32 * MAX_ALLOWED_PROGRAMS)
+        + (1 + 32)
+        + (1 + 4)
+        + 4
+        + (1 + 32)
+        + 8
+        + 1
+        + 8
+        + 1
+        + (1 + 32)
+        + 1
+        + (1 + 32)
+        + (4 + MAX_LABEL_LEN)
+        + (4 + MAX_METADATA_URI_LEN)
+        + 1
+        + (1 + 32)
+        + 8
+        + 56;
+
+    /// Space of the Delegation accounts created with the legacy layout
+    pub const LEGACY_SPACE: usize = 8 + 32 + 32 + 1;
+
+    /// Deserializes a Delegation account in either the current or the legacy layout. Legacy
+    /// delegations get the terms they were honoured under: all permissions, no validity window,
+    /// no program restriction and unlimited uses.
+    pub fn try_deserialize_versioned(data: &[u8]) -> Result<Self> {
+        if data.len() != Self::LEGACY_SPACE {
+            return Self::try_deserialize(&mut &data[..]);
+        }
+        require!(
+            data[..8] == Self::discriminator(),
+            ErrorCode::AccountDiscriminatorMismatch
+        );
+        let legacy = LegacyDelegation::deserialize(&mut &data[8..])
+            .map_err(|_| ErrorCode::AccountDidNotDeserialize)?;
+        Ok(Self {
+            version: LEGACY_DELEGATION_VERSION,
+            master: legacy.master,
+            representative: legacy.representative,
+            authorised: legacy.authorised,
+            permissions: PERMISSION_ALL,
+            ..Self::default()
+        })
+    }
+
+    /// Sets up a newly created, unconfirmed Delegation whose rent was paid by the rent recipient
+    pub fn init(
+        &mut self,
+        master: Pubkey,
+        representative: Pubkey,
+        namespace: Option<Pubkey>,
+        rent_recipient: Pubkey,
+        terms: DelegationTerms,
+    ) -> Result<()> {
+        self.version = DELEGATION_VERSION;
+        self.master = master;
+        self.representative = representative;
+        self.authorised = false;
+        self.namespace = namespace;
+        self.parent = None;
+        self.parent_created_slot = 0;
+        self.depth = 0;
+        self.created_slot = Clock::get()?.slot;
+        self.requested = false;
+        self.original_master = None;
+        self.rotation_allowed = false;
+        self.rent_recipient = Some(rent_recipient);
+        self.label = String::new();
+        self.metadata_uri = String::new();
+        self.session = false;
+        self.role = None;
+        self.revocation_epoch = 0;
+        self.set_terms(terms)
+    }
+
+    /// Validates and sets the terms under which the representative may act
+    pub fn set_terms(&mut self, terms: DelegationTerms) -> Result<()> {
+        require!(terms.permissions != 0, DelegationError::EmptyPermissions);
+        validate_validity_window(terms.valid_from, terms.valid_until)?;
+        validate_allowed_programs(&terms.allowed_programs)?;
+        require!(terms.max_uses != Some(0), DelegationError::InvalidMaxUses);
+        self.permissions = terms.permissions;
+        self.valid_from = terms.valid_from;
+        self.valid_until = terms.valid_until;
+        self.allowed_programs = terms.allowed_programs;
+        self.max_uses = terms.max_uses;
+        self.uses = 0;
+        Ok(())
+    }
+
+    /// Validates and sets the label and metadata URI describing the delegation
+    pub fn set_metadata(&mut self, metadata: DelegationMetadata) -> Result<()> {
+        require!(
+            metadata.label.len() <= MAX_LABEL_LEN,
+            DelegationError::LabelTooLong
+        );
+        require!(
+            metadata.metadata_uri.len() <= MAX_METADATA_URI_LEN,
+            DelegationError::MetadataUriTooLong
+        );
+        self.label = metadata.label;
+        self.metadata_uri = metadata.metadata_uri;
+        Ok(())
+    }
+
+    /// Verifies the delegation authorises the representative to act for the master, in the
+    /// given namespace and within the given program, at the current time
+    pub fn verify(
+        &self,
+        master: &Pubkey,
+        representative: &Pubkey,
+        namespace: Option<&Pubkey>,
+        program_id: Option<&Pubkey>,
+    ) -> Result<()> {
+        require_keys_eq!(*master, self.master);
+        require_keys_eq!(*representative, self.representative);
+        require!(self.authorised, DelegationError::NotAuthorized);
+        self.check_namespace(namespace)?;
+        self.check_validity(Clock::get()?.unix_timestamp)?;
+        self.check_program(program_id)
+    }
+
+    /// Returns the one refunded when the Delegation is closed
+    pub fn refund_recipient(&self) -> Pubkey {
+        self.rent_recipient.unwrap_or(if self.requested {
+            self.representative
+        } else {
+            self.master
+        })
+    }
+
+    /// Returns the key the Delegation address is derived from, along with the representative:
+    /// the parent Delegation for sub-delegations, the original master otherwise
+    pub fn seed_key(&self) -> Pubkey {
+        self.parent
+            .unwrap_or_else(|| self.original_master.unwrap_or(self.master))
+    }
+
+    /// Returns the namespace the Delegation address is derived from, if any. Sub-delegations
+    /// inherit the namespace of their parent, but aren't derived from it.
+    pub fn seed_namespace(&self) -> Option<Pubkey> {
+        self.namespace.filter(|_| self.parent.is_none())
+    }
+
+    /// Returns the address the Delegation is derived from its seed key, representative and
+    /// seed namespace
+    pub fn address(&self) -> Pubkey {
+        match self.seed_namespace() {
+            Some(namespace) => get_namespaced_delegation_address(
+                &self.seed_key(),
+                &self.representative,
+                &namespace,
+            ),
+            None => get_delegation_address(&self.seed_key(), &self.representative),
+        }
+    }
+
+    /// Returns true if every permission in the scope bitmask is granted
+    pub fn has_permissions(&self, scope: u64) -> bool {
+        self.permissions & scope == scope
+    }
+
+    /// Checks that the given unix timestamp falls inside the validity window
+    pub fn check_validity(&self, now: i64) -> Result<()> {
+        if let Some(valid_from) = self.valid_from {
+            require!(now >= valid_from, DelegationError::NotYetActive);
+        }
+        if let Some(valid_until) = self.valid_until {
+            require!(now < valid_until, DelegationError::Expired);
+        }
+        Ok(())
+    }
+
+    /// Checks that the delegation may be honoured by the calling program.
+    /// Restricted delegations are only honoured by callers identifying themselves.
+    pub fn check_program(&self, program_id: Option<&Pubkey>) -> Result<()> {
+        if !self.allowed_programs.is_empty() {
+            require!(
+                matches!(program_id, Some(program_id) if self.allowed_programs.contains(program_id)),
+                DelegationError::ProgramNotAllowed
+            );
+        }
+        Ok(())
+    }
+
+    /// Checks that the delegation was derived from the expected namespace
+    pub fn check_namespace(&self, namespace: Option<&Pubkey>) -> Result<()> {
+        require!(
+            self.namespace.as_ref() == namespace,
+            DelegationError::WrongNamespace
+        );
+        Ok(())
+    }
+}
+
+/// State account tracking how much of a mint the representative of a Delegation
+/// has spent on the master's behalf
+#[account]
+#[derive(Debug)]
+pub struct Allowance {
+    /// The Delegation the allowance belongs to
+    pub delegation: Pubkey,
+    /// The slot in which the Delegation was created, so a Delegation re-created at the same
+    /// address doesn't inherit the allowance
+    pub delegation_created_slot: u64,
+    /// The master of the Delegation
+    pub master: Pubkey,
+    /// The mint the allowance is tracked in, LAMPORTS_MINT for native SOL
+    pub mint: Pubkey,
+    /// The maximum amount the representative may spend
+    pub cap: u64,
+    /// The amount the representative has spent so far
+    pub spent: u64,
+}
+
+impl Allowance {
+    pub const SPACE: usize = 8 + 32 + 8 + 32 + 32 + 8 + 8;
+
+    /// Returns the amount the representative may still spend
+    pub fn remaining(&self) -> u64 {
+        self.cap.saturating_sub(self.spent)
+    }
+
+    /// Records the amount as spent, failing if it exceeds the remaining allowance
+    pub fn consume(&mut self, amount: u64) -> Result<()> {
+        require!(
+            amount <= self.remaining(),
+            DelegationError::AllowanceExceeded
+        );
+        self.spent += amount;
+        Ok(())
+    }
+}
+
+/// State account holding the kill switch of a master over all of its delegations. It doesn't
+/// exist until the master first pauses or revokes its delegations.
+#[account]
+#[derive(Debug)]
+pub struct MasterSettings {
+    /// The master the settings apply to
+    pub master: Pubkey,
+    /// Set while every Delegation of the master is suspended
+    pub paused: bool,
+    /// Number of times the master revoked all of its delegations
+    pub revocation_epoch: u64,
+}
+
+impl MasterSettings {
+    pub const SPACE: usize = 8 + 32 + 1 + 8;
+
+    /// Checks the Delegation is neither paused nor revoked
+    pub fn check_delegation(&self, delegation: &Delegation) -> Result<()> {
+        require!(!self.paused, DelegationError::Paused);
+        require!(
+            delegation.revocation_epoch >= self.revocation_epoch,
+            DelegationError::Revoked
+        );
+        Ok(())
+    }
+}
+
+/// State account describing a named set of permissions and allowed programs, shared by the
+/// delegations holding it
+#[account]
+#[derive(Debug)]
+pub struct Role {
+    /// The master or protocol who created the Role and may update it
+    pub authority: Pubkey,
+    /// Name of the Role, at most MAX_ROLE_NAME_LEN bytes
+    pub name: String,
+    /// Bitmask of the scopes the representatives holding the Role may act in
+    pub permissions: u64,
+    /// Programs which honour the delegations holding the Role, any program if empty
+    pub allowed_programs: Vec<Pubkey>,
+}
+
+impl Role {
+    pub const SPACE: usize = 8 + 32 + (4 + MAX_ROLE_NAME_LEN) + 8 + (4 + 32 * MAX_ALLOWED_PROGRAMS);
+
+    /// Validates and sets the permissions and allowed programs of the Role
+    pub fn set_terms(&mut self, permissions: u64, allowed_programs: Vec<Pubkey>) -> Result<()> {
+        require!(permissions != 0, DelegationError::EmptyPermissions);
+        validate_allowed_programs(&allowed_programs)?;
+        self.permissions = permissions;
+        self.allowed_programs = allowed_programs;
+        Ok(())
+    }
+}
+
+/// State account holding the wallet a master proposed to transfer its delegations to
+#[account]
+#[derive(Debug)]
+pub struct MasterTransfer {
+    /// The master transferring its delegations
+    pub master: Pubkey,
+    /// The wallet which becomes the master of the delegations once it accepts
+    pub new_master: Pubkey,
+}
+
+impl MasterTransfer {
+    pub const SPACE: usize = 8 + 32 + 32;
+}
+
+/// State account listing the Delegations created by a master, so they can be enumerated
+/// without scanning every account owned by the program
+#[account]
+#[derive(Debug)]
+pub struct MasterIndex {
+    /// The master whose Delegations are listed
+    pub master: Pubkey,
+    /// The Delegations created by the master, in no particular order
+    pub delegations: Vec<MasterIndexEntry>,
+}
+
+/// Delegation listed in a MasterIndex
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
+pub struct MasterIndexEntry {
+    /// The representative of the Delegation
+    pub representative: Pubkey,
+    /// The Delegation account
+    pub delegation: Pubkey,
+}
+
+impl MasterIndex {
+    /// Returns the space needed to list the given number of Delegations
+    pub const fn space(len: usize) -> usize {
+        INDEX_ENTRIES_OFFSET + Self::ENTRY_SPACE * len
+    }
+
+    /// Space of a MasterIndexEntry
+    pub const ENTRY_SPACE: usize = 32 + 32;
+}
+
+/// State account listing the Delegations a wallet was made the representative of, so it can
+/// discover whom it may act for from its own key
+#[account]
+#[derive(Debug)]
+pub struct RepresentativeIndex {
+    /// The representative whose Delegations are listed
+    pub representative: Pubkey,
+    /// The Delegations to the representative, in no particular order
+    pub delegations: Vec<RepresentativeIndexEntry>,
+}
+
+/// Delegation listed in a RepresentativeIndex
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
+pub struct RepresentativeIndexEntry {
+    /// The master of the Delegation
+    pub master: Pubkey,
+    /// The Delegation account
+    pub delegation: Pubkey,
+    /// Set once the Delegation is authorised
+    pub confirmed: bool,
+}
+
+impl RepresentativeIndex {
+    /// Returns the space needed to list the given number of Delegations
+    pub const fn space(len: usize) -> usize {
+        INDEX_ENTRIES_OFFSET + Self::ENTRY_SPACE * len
+    }
+
+    /// Space of a RepresentativeIndexEntry
+    pub const ENTRY_SPACE: usize = 32 + 32 + 1;
+}
+
+/// Emitted when a Delegation is created, by the master, a delegator or as a request
+#[event]
+pub struct DelegationCreated {
+    pub master: Pubkey,
+    pub representative: Pubkey,
+    pub delegation: Pubkey,
+    pub namespace: Option<Pubkey>,
+    pub parent: Option<Pubkey>,
+    pub requested: bool,
+    pub slot: u64,
+}
+
+/// Emitted when a Delegation becomes authorised, either confirmed by the representative
+/// or approved by the master
+#[event]
+pub struct DelegationConfirmed {
+    pub master: Pubkey,
+    pub representative: Pubkey,
+    pub delegation: Pubkey,
+    pub confirmed_by: Pubkey,
+    pub slot: u64,
+}
+
+/// Emitted when a Delegation is cancelled by its master or representative
+#[event]
+pub struct DelegationCancelled {
+    pub master: Pubkey,
+    pub representative: Pubkey,
+    pub delegation: Pubkey,
+    pub cancelled_by: Pubkey,
+    pub slot: u64,
+}
+
+/// Emitted when an expired session key Delegation is closed through close_expired_session
+#[event]
+pub struct SessionClosed {
+    pub master: Pubkey,
+    pub representative: Pubkey,
+    pub delegation: Pubkey,
+    pub slot: u64,
+}
+
+/// Emitted when the representative declines a pending delegation
+#[event]
+pub struct DelegationDeclined {
+    pub master: Pubkey,
+    pub representative: Pubkey,
+    pub delegation: Pubkey,
+    pub slot: u64,
+}
+
+/// Emitted when the representative of a Delegation rotates to a new key, moving it to
+/// new_delegation
+#[event]
+pub struct RepresentativeRotated {
+    pub master: Pubkey,
+    pub representative: Pubkey,
+    pub new_representative: Pubkey,
+    pub delegation: Pubkey,
+    pub new_delegation: Pubkey,
+    pub slot: u64,
+}
+
+/// Emitted when a Delegation is transferred to the new master through accept_master_transfer
+#[event]
+pub struct MasterTransferred {
+    pub master: Pubkey,
+    pub new_master: Pubkey,
+    pub representative: Pubkey,
+    pub delegation: Pubkey,
+    pub slot: u64,
+}
+
+/// Program errors
+#[error_code]
+pub enum DelegationError {
+    #[msg("Wrong representative!")]
+    WrongRepresentative,
+    #[msg("Wrong authority!")]
+    WrongMaster,
+    #[msg("Wrong signer!")]
+    WrongSigner,
+    #[msg("Authorization already approved!")]
+    AlreadyAuthorised,
+    #[msg("The account provided has no authority!")]
+    NotAuthorized,
+    #[msg("Delegation must grant at least one permission!")]
+    EmptyPermissions,
+    #[msg("Delegation does not grant the required permission!")]
+    MissingPermission,
+    #[msg("Delegation is not active yet!")]
+    NotYetActive,
+    #[msg("Delegation has expired!")]
+    Expired,
+    #[msg("Invalid delegation validity window!")]
+    InvalidValidityWindow,
+    #[msg("Too many allowed programs!")]
+    TooManyAllowedPrograms,
+    #[msg("Delegation is not honoured by this program!")]
+    ProgramNotAllowed,
+    #[msg("Delegation belongs to a different namespace!")]
+    WrongNamespace,
+    #[msg("Amount exceeds the remaining allowance!")]
+    AllowanceExceeded,
+    #[msg("Delegation must allow at least one use!")]
+    InvalidMaxUses,
+    #[msg("Usage-limited delegations can only be used through use_delegate!")]
+    UsageLimited,
+    #[msg("Sub-delegations can only be checked through check_authorization_chain!")]
+    SubDelegation,
+    #[msg("Delegation chain is too deep!")]
+    DelegationChainTooDeep,
+    #[msg("Invalid delegation chain!")]
+    InvalidDelegationChain,
+    #[msg("Requested delegations need to be approved by the master!")]
+    DelegationRequested,
+    #[msg("Delegation was not requested by the representative!")]
+    NotRequested,
+    #[msg("Delegation already uses the current layout!")]
+    AlreadyMigrated,
+    #[msg("The master paused all of its delegations!")]
+    Paused,
+    #[msg("The master revoked all of its delegations!")]
+    Revoked,
+    #[msg("The new master must differ from the current one!")]
+    SameMaster,
+    #[msg("Invalid remaining accounts!")]
+    InvalidRemainingAccounts,
+    #[msg("The master needs to sign the rotation of the representative!")]
+    RotationNotAllowed,
+    #[msg("Wrong rent recipient!")]
+    WrongRentRecipient,
+    #[msg("Delegation is used up!")]
+    UsedUp,
+    #[msg("Label is too long!")]
+    LabelTooLong,
+    #[msg("Metadata URI is too long!")]
+    MetadataUriTooLong,
+    #[msg("Missing Delegation account!")]
+    MissingDelegationAccount,
+    #[msg("Missing MasterSettings account!")]
+    MissingMasterSettingsAccount,
+    #[msg("The account passed is not a Delegation account!")]
+    InvalidDelegationAccount,
+    #[msg("The Delegation account is not at its derived address!")]
+    WrongDelegationPda,
+    #[msg("Missing Ed25519 signature verification instruction!")]
+    MissingSignatureVerification,
+    #[msg("Invalid Ed25519 signature verification instruction!")]
+    InvalidSignatureVerification,
+    #[msg("Session delegations need to expire within MAX_SESSION_DURATION!")]
+    InvalidSessionDuration,
+    #[msg("Delegation is not a session delegation!")]
+    NotSession,
+    #[msg("Session has not expired yet!")]
+    SessionNotExpired,
+    #[msg("Role name is too long!")]
+    RoleNameTooLong,
+    #[msg("Wrong role authority!")]
+    WrongRoleAuthority,
+    #[msg("Missing Role account of the Delegation!")]
+    MissingRoleAccount,
+    #[msg("The Role passed is not the one of the Delegation!")]
+    WrongRole,
+    #[msg("Roles can't be combined with sub-delegations!")]
+    RoleSubDelegation,
+    #[msg("Allowance was set for an earlier Delegation at this address!")]
+    StaleAllowance,
+    #[msg("Missing MasterIndex or RepresentativeIndex account!")]
+    MissingIndexAccount,
+}
 
 /// Function used to determine if a representative is authorised by master.
-/// If the master is the same as a representative, the delegation_option argument can be None.
-/// If the master is not the same as a representative, Delegation account needs to be passed
+/// If the master is the same as a representative, the delegation_option and
+/// master_settings_option arguments can be None. If the master is not the same as a
+/// representative, Delegation account and the MasterSettings account of the master need to be
+/// passed. Only delegations granting PERMISSION_ALL to any program are honoured, use the other
+/// check functions for scoped, program-restricted or namespaced ones.
 pub fn check_authorization(
     master: &AccountInfo,
     representative: &AccountInfo,
     delegation_option: Option<&AccountInfo>,
+    master_settings_option: Option<&AccountInfo>,
+) -> Result<()> {
+    check_authorization_with_scope(
+        master,
+        representative,
+        delegation_option,
+        master_settings_option,
+        PERMISSION_ALL,
+    )
+}
+
+/// Function used to determine if a representative is authorised by master to act in the
+/// given scope. The scope is a bitmask of PERMISSION_* flags, all of which need to be granted
+/// by the Delegation. A master always has every permission over itself.
+pub fn check_authorization_with_scope(
+    master: &AccountInfo,
+    representative: &AccountInfo,
+    delegation_option: Option<&AccountInfo>,
+    master_settings_option: Option<&AccountInfo>,
+    scope: u64,
 ) -> Result<()> {
-    if master.key() != representative.key() {
-        let delegation_info = delegation_option.expect("Missing Delegation Account");
-        require_keys_eq!(*delegation_info.owner, ID);
-        let delegation = Box::new(
-            Account::<Delegation>::try_from(delegation_info)
-                .expect("Wrong account passed as Delegation account"),
+    if let Some(delegation) = load_authorized_delegation(
+        master,
+        representative,
+        delegation_option,
+        master_settings_option,
+        None,
+        None,
+        None,
+    )? {
+        require!(
+            delegation.has_permissions(scope),
+            DelegationError::MissingPermission
         );
-        require_keys_eq!(master.key(), delegation.master);
-        require_keys_eq!(representative.key(), delegation.representative);
-        require!(delegation.authorised, DelegationError::NotAuthorized);
+    }
+    Ok(())
+}
+
+/// Function used by integrating programs to determine if a representative is authorised by
+/// master to act in the given scope within the calling program. The program_id should be the
+/// ID of the calling program, which needs to be allowed by delegations restricted to a list of
+/// programs. Pass a scope of 0 when no particular permission is required.
+pub fn check_authorization_for_program(
+    master: &AccountInfo,
+    representative: &AccountInfo,
+    delegation_option: Option<&AccountInfo>,
+    master_settings_option: Option<&AccountInfo>,
+    program_id: &Pubkey,
+    scope: u64,
+) -> Result<()> {
+    if let Some(delegation) = load_authorized_delegation(
+        master,
+        representative,
+        delegation_option,
+        master_settings_option,
+        None,
+        None,
+        Some(program_id),
+    )? {
+        require!(
+            delegation.has_permissions(scope),
+            DelegationError::MissingPermission
+        );
+    }
+    Ok(())
+}
+
+/// Function used by integrating programs to determine if a representative is authorised by
+/// master through a Delegation created in the given namespace, to act in the given scope
+/// within the calling program. Works the same way as check_authorization_for_program otherwise.
+pub fn check_namespaced_authorization(
+    master: &AccountInfo,
+    representative: &AccountInfo,
+    delegation_option: Option<&AccountInfo>,
+    master_settings_option: Option<&AccountInfo>,
+    namespace: &Pubkey,
+    program_id: &Pubkey,
+    scope: u64,
+) -> Result<()> {
+    if let Some(delegation) = load_authorized_delegation(
+        master,
+        representative,
+        delegation_option,
+        master_settings_option,
+        None,
+        Some(namespace),
+        Some(program_id),
+    )? {
+        require!(
+            delegation.has_permissions(scope),
+            DelegationError::MissingPermission
+        );
+    }
+    Ok(())
+}
+
+/// Function used by integrating programs to determine if a representative is authorised by
+/// master through a Delegation holding a Role, in which case the permissions and allowed
+/// programs of the Role apply. The role_option is the Role account of the Delegation, and may
+/// be None for delegations without one. Works the same way as check_authorization_for_program
+/// otherwise, and is restricted to the given namespace if any.
+#[allow(clippy::too_many_arguments)]
+pub fn check_authorization_with_role(
+    master: &AccountInfo,
+    representative: &AccountInfo,
+    delegation_option: Option<&AccountInfo>,
+    master_settings_option: Option<&AccountInfo>,
+    role_option: Option<&AccountInfo>,
+    namespace: Option<&Pubkey>,
+    program_id: &Pubkey,
+    scope: u64,
+) -> Result<()> {
+    if let Some(delegation) = load_authorized_delegation(
+        master,
+        representative,
+        delegation_option,
+        master_settings_option,
+        role_option,
+        namespace,
+        Some(program_id),
+    )? {
+        require!(
+            delegation.has_permissions(scope),
+            DelegationError::MissingPermission
+        );
+    }
+    Ok(())
+}
+
+/// Function used by integrating programs to verify a representative is authorised by master,
+/// like check_authorization_for_program does, while counting the use of the Delegation through
+/// a CPI into the use_delegate instruction. Usage-limited delegations need to be checked this way.
+/// The delegation_program is the Delegation Manager program account, the indexes are the
+/// MasterIndex and RepresentativeIndex updated when the Delegation is used up, and the
+/// role_option is the Role account of the Delegation, if it holds one.
+#[cfg(feature = "cpi")]
+#[allow(clippy::too_many_arguments)]
+pub fn use_authorization<'info>(
+    delegation_program: &AccountInfo<'info>,
+    master: &AccountInfo<'info>,
+    representative: &AccountInfo<'info>,
+    delegation_option: Option<&AccountInfo<'info>>,
+    master_settings_option: Option<&AccountInfo<'info>>,
+    master_index_option: Option<&AccountInfo<'info>>,
+    representative_index_option: Option<&AccountInfo<'info>>,
+    role_option: Option<&AccountInfo<'info>>,
+    namespace: Option<Pubkey>,
+    program_id: &Pubkey,
+    scope: u64,
+) -> Result<()> {
+    if master.key() == representative.key() {
+        return Ok(());
+    }
+    let delegation = delegation_option.ok_or(DelegationError::MissingDelegationAccount)?;
+    let master_settings =
+        master_settings_option.ok_or(DelegationError::MissingMasterSettingsAccount)?;
+    let master_index = master_index_option.ok_or(DelegationError::MissingIndexAccount)?;
+    let representative_index =
+        representative_index_option.ok_or(DelegationError::MissingIndexAccount)?;
+    cpi::use_delegate(
+        CpiContext::new(
+            delegation_program.clone(),
+            cpi::accounts::UseDelegation {
+                master: master.clone(),
+                representative: representative.clone(),
+                delegation: delegation.clone(),
+                master_settings: master_settings.clone(),
+                master_index: master_index.clone(),
+                representative_index: representative_index.clone(),
+            },
+        )
+        .with_remaining_accounts(role_option.into_iter().cloned().collect()),
+        namespace,
+        Some(*program_id),
+        scope,
+    )
+}
+
+/// Loads a Delegation account owned by this program, in either the current or the legacy layout,
+/// and verifies it is at the address derived from its seeds
+fn load_delegation(delegation_info: &AccountInfo) -> Result<Box<Delegation>> {
+    require_keys_eq!(
+        *delegation_info.owner,
+        ID,
+        DelegationError::InvalidDelegationAccount
+    );
+    let delegation = Delegation::try_deserialize_versioned(&delegation_info.try_borrow_data()?)
+        .map_err(|_| DelegationError::InvalidDelegationAccount)?;
+    require_keys_eq!(
+        delegation_info.key(),
+        delegation.address(),
+        DelegationError::WrongDelegationPda
+    );
+    Ok(Box::new(delegation))
+}
+
+/// Loads the Delegation account and verifies it authorises the representative, under the terms
+/// of its Role if it holds one. Returns None if the master is the same as the representative.
+fn load_authorized_delegation(
+    master: &AccountInfo,
+    representative: &AccountInfo,
+    delegation_option: Option<&AccountInfo>,
+    master_settings_option: Option<&AccountInfo>,
+    role_option: Option<&AccountInfo>,
+    namespace: Option<&Pubkey>,
+    program_id: Option<&Pubkey>,
+) -> Result<Option<Box<Delegation>>> {
+    if master.key() == representative.key() {
+        return Ok(None);
+    }
+    let delegation_info = delegation_option.ok_or(DelegationError::MissingDelegationAccount)?;
+    let master_settings =
+        master_settings_option.ok_or(DelegationError::MissingMasterSettingsAccount)?;
+    let mut delegation = load_delegation(delegation_info)?;
+    if delegation.parent.is_none() {
+        // the Delegation needs to be the one derived for the master and representative passed
+        let seed_master = delegation.original_master.unwrap_or_else(|| master.key());
+        let address = match delegation.seed_namespace() {
+            Some(namespace) => {
+                get_namespaced_delegation_address(&seed_master, &representative.key(), &namespace)
+            }
+            None => get_delegation_address(&seed_master, &representative.key()),
+        };
+        require_keys_eq!(
+            delegation_info.key(),
+            address,
+            DelegationError::WrongDelegationPda
+        );
+    }
+    apply_role(&mut delegation, role_option)?;
+    delegation.verify(&master.key(), &representative.key(), namespace, program_id)?;
+    check_master_settings(master_settings, &master.key(), &delegation)?;
+    require!(delegation.max_uses.is_none(), DelegationError::UsageLimited);
+    require!(delegation.parent.is_none(), DelegationError::SubDelegation);
+    Ok(Some(delegation))
+}
+
+/// Function used to determine if a representative is authorised by master through a chain of
+/// sub-delegations. The chain holds the Delegation accounts ordered from the one created by the
+/// master to the one held by the representative, and may contain at most max_depth
+/// re-delegations. Every link needs to be confirmed, active, and honoured in the given namespace
+/// and program, and to grant the required scope. The master_settings of the original master
+/// apply to the whole chain.
+#[allow(clippy::too_many_arguments)]
+pub fn check_authorization_chain(
+    master: &AccountInfo,
+    representative: &AccountInfo,
+    chain: &[AccountInfo],
+    master_settings: &AccountInfo,
+    namespace: Option<&Pubkey>,
+    program_id: Option<&Pubkey>,
+    scope: u64,
+    max_depth: u8,
+) -> Result<()> {
+    if master.key() == representative.key() {
+        return Ok(());
+    }
+    require!(!chain.is_empty(), DelegationError::InvalidDelegationChain);
+    require!(
+        chain.len() <= usize::from(max_depth.min(MAX_DELEGATION_DEPTH)) + 1,
+        DelegationError::DelegationChainTooDeep
+    );
+
+    let mut delegator = master.key();
+    let mut parent: Option<(Pubkey, u64)> = None;
+    for delegation_info in chain {
+        let delegation = load_delegation(delegation_info)?;
+        require!(
+            delegation.role.is_none(),
+            DelegationError::RoleSubDelegation
+        );
+        delegation.verify(
+            &delegator,
+            &delegation.representative,
+            namespace,
+            program_id,
+        )?;
+        require!(delegation.max_uses.is_none(), DelegationError::UsageLimited);
+        require!(
+            delegation.has_permissions(scope),
+            DelegationError::MissingPermission
+        );
+        match parent {
+            None => {
+                require!(
+                    delegation.parent.is_none(),
+                    DelegationError::InvalidDelegationChain
+                );
+                check_master_settings(master_settings, &master.key(), &delegation)?;
+            }
+            Some((parent_key, parent_created_slot)) => require!(
+                delegation.parent == Some(parent_key)
+                    && delegation.parent_created_slot == parent_created_slot,
+                DelegationError::InvalidDelegationChain
+            ),
+        }
+        delegator = delegation.representative;
+        parent = Some((delegation_info.key(), delegation.created_slot));
+    }
+    require_keys_eq!(representative.key(), delegator);
+    Ok(())
+}
+
+/// Replaces the permissions and allowed programs of the Delegation with the ones of its Role.
+/// The Role account needs to be passed for delegations holding one, and is ignored otherwise.
+fn apply_role(delegation: &mut Delegation, role_option: Option<&AccountInfo>) -> Result<()> {
+    let role_key = match delegation.role {
+        Some(role_key) => role_key,
+        None => return Ok(()),
+    };
+    let role_info = role_option.ok_or(DelegationError::MissingRoleAccount)?;
+    require_keys_eq!(role_info.key(), role_key, DelegationError::WrongRole);
+    let role = Account::<Role>::try_from(role_info)?;
+    delegation.permissions = role.permissions;
+    delegation.allowed_programs = role.allowed_programs.clone();
+    Ok(())
+}
+
+/// Returns the revocation epoch of the master, 0 if its MasterSettings don't exist yet
+fn revocation_epoch(master_settings: &AccountInfo) -> Result<u64> {
+    if master_settings.data_is_empty() {
+        return Ok(0);
+    }
+    Ok(Account::<MasterSettings>::try_from(master_settings)?.revocation_epoch)
+}
+
+/// Checks the master hasn't paused or revoked the Delegation. The MasterSettings account needs
+/// to be derived from the master, and may not exist if the master never used its kill switch.
+fn check_master_settings(
+    master_settings: &AccountInfo,
+    master: &Pubkey,
+    delegation: &Delegation,
+) -> Result<()> {
+    require_keys_eq!(
+        master_settings.key(),
+        get_master_settings_address(master),
+        ErrorCode::ConstraintSeeds
+    );
+    if master_settings.data_is_empty() {
+        return Ok(());
+    }
+    Account::<MasterSettings>::try_from(master_settings)?.check_delegation(delegation)
+}
+
+/// Checks the instruction preceding the current one is an Ed25519 program instruction verifying
+/// a single signature of the signer over the message. The signature, public key and message
+/// need to be held by the Ed25519 instruction itself, rather than pointing at another one.
+fn verify_ed25519_signature(
+    instructions: &AccountInfo,
+    signer: &Pubkey,
+    message: &[u8],
+) -> Result<()> {
+    let current_index = load_current_index_checked(instructions)?;
+    require!(
+        current_index > 0,
+        DelegationError::MissingSignatureVerification
+    );
+    let instruction = load_instruction_at_checked(usize::from(current_index - 1), instructions)?;
+    require_keys_eq!(
+        instruction.program_id,
+        ed25519_program::ID,
+        DelegationError::MissingSignatureVerification
+    );
+
+    // one signature count and padding byte, followed by a single set of 7 u16 offsets
+    let data = &instruction.data;
+    require!(
+        data.len() >= 16 && data[0] == 1,
+        DelegationError::InvalidSignatureVerification
+    );
+    let offset = |i: usize| usize::from(u16::from_le_bytes([data[2 + 2 * i], data[3 + 2 * i]]));
+    let (public_key_offset, message_offset, message_size) = (offset(2), offset(4), offset(5));
+    require!(
+        [offset(1), offset(3), offset(6)]
+            .iter()
+            .all(|index| *index == usize::from(u16::MAX)),
+        DelegationError::InvalidSignatureVerification
+    );
+    require!(
+        data.get(public_key_offset..public_key_offset + 32) == Some(signer.as_ref())
+            && data.get(message_offset..message_offset + message_size) == Some(message),
+        DelegationError::InvalidSignatureVerification
+    );
+    Ok(())
+}
+
+/// Validates the list of programs a Delegation is restricted to
+fn validate_allowed_programs(allowed_programs: &[Pubkey]) -> Result<()> {
+    require!(
+        allowed_programs.len() <= MAX_ALLOWED_PROGRAMS,
+        DelegationError::TooManyAllowedPrograms
+    );
+    Ok(())
+}
+
+/// Lists the new Delegation in the index of its master, creating or growing the index at the
+/// payer's expense. The index is created manually rather than with init_if_needed, which
+/// rejects existing accounts whose size differs from the initial one.
+fn add_to_master_index<'info>(
+    master_index: &AccountInfo<'info>,
+    bump: u8,
+    master: &Pubkey,
+    representative: &Pubkey,
+    delegation: &Pubkey,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+) -> Result<()> {
+    if master_index.data_is_empty() {
+        create_program_account(
+            master_index,
+            &[MASTER_INDEX_SEED, master.as_ref(), &[bump]],
+            &MasterIndex {
+                master: *master,
+                delegations: vec![],
+            },
+            MasterIndex::space(0),
+            payer,
+            system_program,
+        )?;
+    }
+
+    let entry = MasterIndexEntry {
+        representative: *representative,
+        delegation: *delegation,
+    };
+    push_index_entry(master_index, &entry.try_to_vec()?, payer, system_program)
+}
+
+/// Lists the new Delegation in the index of its representative, creating or growing the index
+/// at the payer's expense like add_to_master_index
+fn add_to_representative_index<'info>(
+    representative_index: &AccountInfo<'info>,
+    bump: u8,
+    representative: &Pubkey,
+    entry: RepresentativeIndexEntry,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+) -> Result<()> {
+    if representative_index.data_is_empty() {
+        create_program_account(
+            representative_index,
+            &[REPRESENTATIVE_INDEX_SEED, representative.as_ref(), &[bump]],
+            &RepresentativeIndex {
+                representative: *representative,
+                delegations: vec![],
+            },
+            RepresentativeIndex::space(0),
+            payer,
+            system_program,
+        )?;
+    }
+
+    push_index_entry(
+        representative_index,
+        &entry.try_to_vec()?,
+        payer,
+        system_program,
+    )
+}
+
+/// Marks the Delegation as confirmed in the index of its representative, if the index exists
+fn confirm_in_representative_index(
+    representative_index: &AccountInfo,
+    delegation: &Pubkey,
+) -> Result<()> {
+    update_index_entry(
+        representative_index,
+        RepresentativeIndex::ENTRY_SPACE,
+        delegation,
+        |entry| entry[64] = 1,
+    )
+}
+
+/// Updates the master of the transferred Delegation in the index of its representative, if the
+/// index exists
+fn transfer_in_representative_index(
+    representative_index: &AccountInfo,
+    delegation: &Pubkey,
+    master: &Pubkey,
+) -> Result<()> {
+    update_index_entry(
+        representative_index,
+        RepresentativeIndex::ENTRY_SPACE,
+        delegation,
+        |entry| entry[..32].copy_from_slice(master.as_ref()),
+    )
+}
+
+/// Removes the closed Delegation from the index of its representative, if the index exists
+fn remove_from_representative_index(
+    representative_index: &AccountInfo,
+    delegation: &Pubkey,
+) -> Result<()> {
+    remove_index_entry(
+        representative_index,
+        RepresentativeIndex::ENTRY_SPACE,
+        delegation,
+    )
+}
+
+/// Creates a PDA owned by this program with its initial content. Anchor's init isn't used as
+/// the PDA may already hold lamports, which create_account would reject.
+fn create_program_account<'info, T: AccountSerialize>(
+    account: &AccountInfo<'info>,
+    seeds: &[&[u8]],
+    content: &T,
+    space: usize,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+) -> Result<()> {
+    fund_rent(account, payer, system_program, space)?;
+    system_program::allocate(
+        CpiContext::new_with_signer(
+            system_program.clone(),
+            system_program::Allocate {
+                account_to_allocate: account.clone(),
+            },
+            &[seeds],
+        ),
+        space as u64,
+    )?;
+    system_program::assign(
+        CpiContext::new_with_signer(
+            system_program.clone(),
+            system_program::Assign {
+                account_to_assign: account.clone(),
+            },
+            &[seeds],
+        ),
+        &ID,
+    )?;
+    let mut data = account.try_borrow_mut_data()?;
+    let mut writer: &mut [u8] = &mut data;
+    content.try_serialize(&mut writer)
+}
+
+/// Checks the Delegation is cancelled by its master or representative, removes it from their
+/// indexes and emits DelegationCancelled. The Delegation account is closed by the caller.
+fn cancel_delegation(
+    delegation: &Delegation,
+    address: &Pubkey,
+    cancelled_by: &Pubkey,
+    master_index: &AccountInfo,
+    representative_index: &AccountInfo,
+) -> Result<()> {
+    require!(
+        *cancelled_by == delegation.master || *cancelled_by == delegation.representative,
+        DelegationError::WrongSigner
+    );
+    remove_from_master_index(master_index, address)?;
+    remove_from_representative_index(representative_index, address)?;
+
+    emit!(DelegationCancelled {
+        master: delegation.master,
+        representative: delegation.representative,
+        delegation: *address,
+        cancelled_by: *cancelled_by,
+        slot: Clock::get()?.slot,
+    });
+    Ok(())
+}
+
+/// Closes a program account like anchor's close constraint does, refunding its rent to the
+/// recipient. Used for Delegation accounts which may be in the legacy layout.
+fn close_account<'info>(
+    account: &AccountInfo<'info>,
+    recipient: &AccountInfo<'info>,
+) -> Result<()> {
+    let lamports = account.lamports();
+    **recipient.try_borrow_mut_lamports()? = recipient
+        .lamports()
+        .checked_add(lamports)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    **account.try_borrow_mut_lamports()? = 0;
+    account.assign(&System::id());
+    account.realloc(0, false)?;
+    Ok(())
+}
+
+/// Removes the closed Delegation from the index of its master, if the index exists
+fn remove_from_master_index(master_index: &AccountInfo, delegation: &Pubkey) -> Result<()> {
+    remove_index_entry(master_index, MasterIndex::ENTRY_SPACE, delegation)
+}
+
+/// Offset of the length of the delegations of a MasterIndex or RepresentativeIndex
+const INDEX_LEN_OFFSET: usize = 8 + 32;
+/// Offset of the first entry of a MasterIndex or RepresentativeIndex
+const INDEX_ENTRIES_OFFSET: usize = INDEX_LEN_OFFSET + 4;
+/// Offset of the Delegation key within the entries of both indexes
+const INDEX_ENTRY_DELEGATION_OFFSET: usize = 32;
+
+/// Returns the number of entries of a MasterIndex or RepresentativeIndex from its data.
+/// Indexes are edited in place rather than deserialized, so their upkeep doesn't depend on how
+/// many Delegations they list.
+fn index_len(data: &[u8]) -> usize {
+    let mut len = [0; 4];
+    len.copy_from_slice(&data[INDEX_LEN_OFFSET..INDEX_ENTRIES_OFFSET]);
+    u32::from_le_bytes(len) as usize
+}
+
+/// Returns the number of entries of the index and the data offset of the entry listing the
+/// Delegation, if any
+fn find_index_entry(
+    data: &[u8],
+    entry_space: usize,
+    delegation: &Pubkey,
+) -> (usize, Option<usize>) {
+    let len = index_len(data).min(data.len().saturating_sub(INDEX_ENTRIES_OFFSET) / entry_space);
+    let offset = (0..len)
+        .map(|i| INDEX_ENTRIES_OFFSET + i * entry_space)
+        .find(|offset| {
+            let key = offset + INDEX_ENTRY_DELEGATION_OFFSET;
+            &data[key..key + 32] == delegation.as_ref()
+        });
+    (len, offset)
+}
+
+/// Appends the serialized entry to the index, growing it at the payer's expense, unless the
+/// index already lists MAX_INDEX_ENTRIES Delegations
+fn push_index_entry<'info>(
+    index: &AccountInfo<'info>,
+    entry: &[u8],
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+) -> Result<()> {
+    let len = index_len(&index.try_borrow_data()?);
+    if len >= MAX_INDEX_ENTRIES {
+        return Ok(());
+    }
+    let offset = INDEX_ENTRIES_OFFSET + len * entry.len();
+    grow_account(index, payer, system_program, offset + entry.len())?;
+
+    let mut data = index.try_borrow_mut_data()?;
+    data[offset..offset + entry.len()].copy_from_slice(entry);
+    data[INDEX_LEN_OFFSET..INDEX_ENTRIES_OFFSET].copy_from_slice(&(len as u32 + 1).to_le_bytes());
+    Ok(())
+}
+
+/// Edits the entry listing the Delegation in place, if the index exists and lists it
+fn update_index_entry(
+    index: &AccountInfo,
+    entry_space: usize,
+    delegation: &Pubkey,
+    update: impl FnOnce(&mut [u8]),
+) -> Result<()> {
+    if index.data_is_empty() || index.owner != &ID {
+        return Ok(());
+    }
+    let mut data = index.try_borrow_mut_data()?;
+    if let (_, Some(offset)) = find_index_entry(&data, entry_space, delegation) {
+        update(&mut data[offset..offset + entry_space]);
+    }
+    Ok(())
+}
+
+/// Removes the Delegation from the index, if listed, by moving the last entry in its place.
+/// The index keeps its space for the next entries. Never fails on a missing index, so the
+/// Delegation can always be closed.
+fn remove_index_entry(index: &AccountInfo, entry_space: usize, delegation: &Pubkey) -> Result<()> {
+    if index.data_is_empty() || index.owner != &ID {
+        return Ok(());
+    }
+    let mut data = index.try_borrow_mut_data()?;
+    if let (len, Some(offset)) = find_index_entry(&data, entry_space, delegation) {
+        let last = INDEX_ENTRIES_OFFSET + (len - 1) * entry_space;
+        data.copy_within(last..last + entry_space, offset);
+        data[last..last + entry_space].fill(0);
+        data[INDEX_LEN_OFFSET..INDEX_ENTRIES_OFFSET]
+            .copy_from_slice(&(len as u32 - 1).to_le_bytes());
+    }
+    Ok(())
+}
+
+/// Reallocates the account to the given space if it is smaller, with the payer funding the rent
+fn grow_account<'info>(
+    account: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    space: usize,
+) -> Result<()> {
+    if account.data_len() >= space {
+        return Ok(());
+    }
+    fund_rent(account, payer, system_program, space)?;
+    account.realloc(space, true)?;
+    Ok(())
+}
+
+/// Transfers the lamports the account is missing to be rent exempt with the given space
+fn fund_rent<'info>(
+    account: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    space: usize,
+) -> Result<()> {
+    let rent = Rent::get()?
+        .minimum_balance(space)
+        .saturating_sub(account.lamports());
+    if rent > 0 {
+        system_program::transfer(
+            CpiContext::new(
+                system_program.clone(),
+                system_program::Transfer {
+                    from: payer.clone(),
+                    to: account.clone(),
+                },
+            ),
+            rent,
+        )?;
+    }
+    Ok(())
+}
+
+/// Emits the DelegationCreated event for a newly created Delegation
+fn emit_created(address: Pubkey, delegation: &Delegation) -> Result<()> {
+    emit!(DelegationCreated {
+        master: delegation.master,
+        representative: delegation.representative,
+        delegation: address,
+        namespace: delegation.namespace,
+        parent: delegation.parent,
+        requested: delegation.requested,
+        slot: delegation.created_slot,
+    });
+    Ok(())
+}
+
+/// Validates the validity window passed when creating a Delegation.
+/// The window can't end before it starts, nor in the past.
+fn validate_validity_window(valid_from: Option<i64>, valid_until: Option<i64>) -> Result<()> {
+    if let Some(valid_until) = valid_until {
+        require!(
+            valid_until > Clock::get()?.unix_timestamp,
+            DelegationError::InvalidValidityWindow
+        );
+        if let Some(valid_from) = valid_from {
+            require!(
+                valid_from < valid_until,
+                DelegationError::InvalidValidityWindow
+            );
+        }
     }
     Ok(())
 }
@@ -167,3 +3221,96 @@ pub fn get_delegation_address_seeds<'a>(
 ) -> [&'a [u8]; 3] {
     [AUTHORIZE_SEED, &master.as_ref(), &representative.as_ref()]
 }
+
+pub fn get_namespaced_delegation_address(
+    master: &Pubkey,
+    representative: &Pubkey,
+    namespace: &Pubkey,
+) -> Pubkey {
+    Pubkey::find_program_address(
+        &get_namespaced_delegation_address_seeds(master, representative, namespace),
+        &ID,
+    )
+    .0
+}
+
+pub fn get_namespaced_delegation_address_seeds<'a>(
+    master: &'a Pubkey,
+    representative: &'a Pubkey,
+    namespace: &'a Pubkey,
+) -> [&'a [u8]; 4] {
+    [
+        AUTHORIZE_SEED,
+        master.as_ref(),
+        representative.as_ref(),
+        namespace.as_ref(),
+    ]
+}
+
+pub fn get_sub_delegation_address(parent_delegation: &Pubkey, representative: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &get_delegation_address_seeds(parent_delegation, representative),
+        &ID,
+    )
+    .0
+}
+
+pub fn get_master_index_address(master: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&get_master_index_address_seeds(master), &ID).0
+}
+
+pub fn get_master_index_address_seeds(master: &Pubkey) -> [&[u8]; 2] {
+    [MASTER_INDEX_SEED, master.as_ref()]
+}
+
+pub fn get_representative_index_address(representative: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&get_representative_index_address_seeds(representative), &ID).0
+}
+
+pub fn get_representative_index_address_seeds(representative: &Pubkey) -> [&[u8]; 2] {
+    [REPRESENTATIVE_INDEX_SEED, representative.as_ref()]
+}
+
+pub fn get_master_settings_address(master: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&get_master_settings_address_seeds(master), &ID).0
+}
+
+pub fn get_master_settings_address_seeds(master: &Pubkey) -> [&[u8]; 2] {
+    [MASTER_SETTINGS_SEED, master.as_ref()]
+}
+
+pub fn get_master_transfer_address(master: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&get_master_transfer_address_seeds(master), &ID).0
+}
+
+pub fn get_master_transfer_address_seeds(master: &Pubkey) -> [&[u8]; 2] {
+    [MASTER_TRANSFER_SEED, master.as_ref()]
+}
+
+pub fn get_role_address(authority: &Pubkey, name: &str) -> Pubkey {
+    let name_hash = hash(name.as_bytes());
+    Pubkey::find_program_address(&get_role_address_seeds(authority, &name_hash), &ID).0
+}
+
+pub fn get_role_address_seeds<'a>(authority: &'a Pubkey, name_hash: &'a Hash) -> [&'a [u8]; 3] {
+    [ROLE_SEED, authority.as_ref(), name_hash.as_ref()]
+}
+
+/// Returns the message a representative signs off-chain to confirm the Delegation with
+/// confirm_delegate_with_signature. The nonce is the slot the Delegation was created in, so the
+/// signature can't confirm a Delegation re-created at the same address later on.
+pub fn get_confirmation_message(
+    delegation: &Pubkey,
+    master: &Pubkey,
+    representative: &Pubkey,
+    nonce: u64,
+) -> Vec<u8> {
+    [
+        ID.as_ref(),
+        delegation.as_ref(),
+        master.as_ref(),
+        representative.as_ref(),
+        &nonce.to_le_bytes(),
+    ]
+    .concat()
+}
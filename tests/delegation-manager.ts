@@ -1,12 +1,17 @@
 import * as anchor from "@project-serum/anchor";
 import { Program } from "@project-serum/anchor";
 import {
+  Ed25519Program,
   Keypair,
   LAMPORTS_PER_SOL,
   PublicKey,
+  SYSVAR_CLOCK_PUBKEY,
+  SYSVAR_INSTRUCTIONS_PUBKEY,
   SystemProgram,
 } from "@solana/web3.js";
 import { assert } from "chai";
+import { createHash } from "crypto";
+import * as fs from "fs";
 import { DelegationManager as DelegateManager } from "../target/types/delegation_manager";
 import { Example } from "../target/types/example";
 
@@ -18,79 +23,198 @@ describe("delegation-manager", () => {
   const example = anchor.workspace.Example as Program<Example>;
   const connection = anchor.getProvider().connection;
 
-  it("Initialize, confirm, cancel by authority", async () => {
-    const master = Keypair.generate();
-    const representative = Keypair.generate();
+  const PERMISSION_CLAIM = new anchor.BN(1 << 1);
+  const PERMISSION_ALL = new anchor.BN("ffffffffffffffff", 16);
+
+  const terms = (overrides = {}) => ({
+    permissions: PERMISSION_ALL,
+    validFrom: null,
+    validUntil: null,
+    allowedPrograms: [],
+    maxUses: null,
+    ...overrides,
+  });
+
+  const metadata = (overrides = {}) => ({
+    label: "",
+    metadataUri: "",
+    ...overrides,
+  });
+
+  const masterIndexOf = (master: PublicKey) =>
+    PublicKey.findProgramAddressSync(
+      [Buffer.from("master_index"), master.toBuffer()],
+      program.programId
+    )[0];
+
+  const representativeIndexOf = (representative: PublicKey) =>
+    PublicKey.findProgramAddressSync(
+      [Buffer.from("representative_index"), representative.toBuffer()],
+      program.programId
+    )[0];
+
+  const masterSettingsOf = (master: PublicKey) =>
+    PublicKey.findProgramAddressSync(
+      [Buffer.from("master_settings"), master.toBuffer()],
+      program.programId
+    )[0];
+
+  // unix_timestamp of the Clock sysvar, the time check_validity compares against
+  const clockTime = async () => {
+    const clock = await connection.getAccountInfo(SYSVAR_CLOCK_PUBKEY);
+    return Number(clock.data.readBigInt64LE(32));
+  };
+
+  const waitForClock = async (timestamp: number) => {
+    while ((await clockTime()) < timestamp) {
+      await new Promise((resolve) => setTimeout(resolve, 500));
+    }
+  };
 
-    await connection.confirmTransaction(
-      await connection.requestAirdrop(master.publicKey, LAMPORTS_PER_SOL)
+  const airdrop = async (wallet: PublicKey) =>
+    connection.confirmTransaction(
+      await connection.requestAirdrop(wallet, LAMPORTS_PER_SOL)
     );
-    await connection.confirmTransaction(
-      await connection.requestAirdrop(
-        representative.publicKey,
-        LAMPORTS_PER_SOL
+
+  // keypairs of the accounts preloaded by Anchor.toml
+  const fixtureKeypair = (name: string) =>
+    Keypair.fromSecretKey(
+      Uint8Array.from(
+        JSON.parse(fs.readFileSync(`tests/fixtures/${name}.json`, "utf8"))
       )
     );
 
-    const [delegation] = PublicKey.findProgramAddressSync(
+  const delegationOf = (master: PublicKey, representative: PublicKey) =>
+    PublicKey.findProgramAddressSync(
+      [Buffer.from("authorize"), master.toBuffer(), representative.toBuffer()],
+      program.programId
+    )[0];
+
+  // roles are derived from the hash of their name, which may not fit in a seed
+  const roleOf = (authority: PublicKey, name: string) =>
+    PublicKey.findProgramAddressSync(
       [
-        Buffer.from("authorize"),
-        master.publicKey.toBuffer(),
-        representative.publicKey.toBuffer(),
+        Buffer.from("role"),
+        authority.toBuffer(),
+        createHash("sha256").update(name).digest(),
       ],
       program.programId
-    );
+    )[0];
 
-    await program.methods
-      .initializeDelegate()
+  const initializeDelegate = (
+    master: Keypair,
+    representative: PublicKey,
+    delegationTerms = terms(),
+    delegationMetadata = metadata()
+  ) =>
+    program.methods
+      .initializeDelegate(delegationTerms, delegationMetadata)
       .accounts({
         master: master.publicKey,
-        representative: representative.publicKey,
-        delegation,
+        payer: master.publicKey,
+        representative,
+        delegation: delegationOf(master.publicKey, representative),
+        masterIndex: masterIndexOf(master.publicKey),
+        representativeIndex: representativeIndexOf(representative),
+        masterSettings: masterSettingsOf(master.publicKey),
         systemProgram: SystemProgram.programId,
       })
       .signers([master])
       .rpc();
 
-    assert.deepEqual(await program.account.delegation.fetch(delegation), {
-      master: master.publicKey,
-      representative: representative.publicKey,
-      authorised: false,
-    });
-
-    await program.methods
+  const confirmDelegate = (representative: Keypair, delegation: PublicKey) =>
+    program.methods
       .confirmDelegate()
       .accounts({
         representative: representative.publicKey,
         delegation,
+        representativeIndex: representativeIndexOf(representative.publicKey),
         systemProgram: SystemProgram.programId,
       })
       .signers([representative])
       .rpc();
 
-    assert.deepEqual(await program.account.delegation.fetch(delegation), {
-      master: master.publicKey,
-      representative: representative.publicKey,
-      authorised: true,
-    });
-
-    await program.methods
+  // cancels the delegation signed by its master or representative
+  const cancelDelegate = (
+    signer: Keypair,
+    master: PublicKey,
+    representative: PublicKey,
+    delegation = delegationOf(master, representative)
+  ) =>
+    program.methods
       .cancelDelegate()
       .accounts({
         delegation,
+        rentRecipient: master,
+        masterIndex: masterIndexOf(master),
+        representativeIndex: representativeIndexOf(representative),
         systemProgram: SystemProgram.programId,
       })
       .remainingAccounts([
-        { pubkey: master.publicKey, isSigner: true, isWritable: true },
         {
-          pubkey: representative.publicKey,
-          isSigner: false,
+          pubkey: master,
+          isSigner: signer.publicKey.equals(master),
+          isWritable: true,
+        },
+        {
+          pubkey: representative,
+          isSigner: signer.publicKey.equals(representative),
           isWritable: false,
         },
       ])
-      .signers([master])
+      .signers([signer])
       .rpc();
 
+  // funds a new master and delegates to a new representative, who confirms it
+  const createConfirmedDelegation = async (delegationTerms = terms()) => {
+    const master = Keypair.generate();
+    const representative = Keypair.generate();
+    await airdrop(master.publicKey);
+    await initializeDelegate(master, representative.publicKey, delegationTerms);
+    const delegation = delegationOf(master.publicKey, representative.publicKey);
+    await confirmDelegate(representative, delegation);
+    return { master, representative, delegation };
+  };
+
+  const fetchDelegation = async (delegation: PublicKey) => {
+    const account = await program.account.delegation.fetch(delegation);
+    return {
+      master: account.master,
+      representative: account.representative,
+      authorised: account.authorised,
+      permissions: account.permissions.toString(),
+    };
+  };
+
+  it("Initialize, confirm, cancel by authority", async () => {
+    const master = Keypair.generate();
+    const representative = Keypair.generate();
+
+    await airdrop(master.publicKey);
+    await airdrop(representative.publicKey);
+
+    const delegation = delegationOf(master.publicKey, representative.publicKey);
+
+    await initializeDelegate(master, representative.publicKey);
+
+    assert.deepEqual(await fetchDelegation(delegation), {
+      master: master.publicKey,
+      representative: representative.publicKey,
+      authorised: false,
+      permissions: PERMISSION_ALL.toString(),
+    });
+
+    await confirmDelegate(representative, delegation);
+
+    assert.deepEqual(await fetchDelegation(delegation), {
+      master: master.publicKey,
+      representative: representative.publicKey,
+      authorised: true,
+      permissions: PERMISSION_ALL.toString(),
+    });
+
+    await cancelDelegate(master, master.publicKey, representative.publicKey);
+
     try {
       await program.account.delegation.fetch(delegation);
       assert(false);
@@ -103,70 +227,34 @@ describe("delegation-manager", () => {
     const master = Keypair.generate();
     const representative = Keypair.generate();
 
-    await connection.confirmTransaction(
-      await connection.requestAirdrop(master.publicKey, LAMPORTS_PER_SOL)
-    );
-    await connection.confirmTransaction(
-      await connection.requestAirdrop(
-        representative.publicKey,
-        LAMPORTS_PER_SOL
-      )
-    );
+    await airdrop(master.publicKey);
+    await airdrop(representative.publicKey);
 
-    const [delegation] = PublicKey.findProgramAddressSync(
-      [
-        Buffer.from("authorize"),
-        master.publicKey.toBuffer(),
-        representative.publicKey.toBuffer(),
-      ],
-      program.programId
-    );
+    const delegation = delegationOf(master.publicKey, representative.publicKey);
 
-    await program.methods
-      .initializeDelegate()
-      .accounts({
-        master: master.publicKey,
-        representative: representative.publicKey,
-        delegation,
-        systemProgram: SystemProgram.programId,
-      })
-      .signers([master])
-      .rpc();
+    await initializeDelegate(master, representative.publicKey);
 
-    assert.deepEqual(await program.account.delegation.fetch(delegation), {
+    assert.deepEqual(await fetchDelegation(delegation), {
       master: master.publicKey,
       representative: representative.publicKey,
       authorised: false,
+      permissions: PERMISSION_ALL.toString(),
     });
 
-    await program.methods
-      .confirmDelegate()
-      .accounts({
-        representative: representative.publicKey,
-        delegation,
-        systemProgram: SystemProgram.programId,
-      })
-      .signers([representative])
-      .rpc();
+    await confirmDelegate(representative, delegation);
 
-    assert.deepEqual(await program.account.delegation.fetch(delegation), {
+    assert.deepEqual(await fetchDelegation(delegation), {
       master: master.publicKey,
       representative: representative.publicKey,
       authorised: true,
+      permissions: PERMISSION_ALL.toString(),
     });
 
-    await program.methods
-      .cancelDelegate()
-      .accounts({
-        delegation,
-        systemProgram: SystemProgram.programId,
-      })
-      .remainingAccounts([
-        { pubkey: master.publicKey, isSigner: false, isWritable: true },
-        { pubkey: representative.publicKey, isSigner: true, isWritable: false },
-      ])
-      .signers([representative])
-      .rpc();
+    await cancelDelegate(
+      representative,
+      master.publicKey,
+      representative.publicKey
+    );
 
     try {
       await program.account.delegation.fetch(delegation);
@@ -181,59 +269,28 @@ describe("delegation-manager", () => {
     const representative = Keypair.generate();
     const hacker = Keypair.generate();
 
-    await connection.confirmTransaction(
-      await connection.requestAirdrop(master.publicKey, LAMPORTS_PER_SOL)
-    );
-    await connection.confirmTransaction(
-      await connection.requestAirdrop(
-        representative.publicKey,
-        LAMPORTS_PER_SOL
-      )
-    );
-    await connection.confirmTransaction(
-      await connection.requestAirdrop(hacker.publicKey, LAMPORTS_PER_SOL)
-    );
+    await airdrop(master.publicKey);
+    await airdrop(representative.publicKey);
+    await airdrop(hacker.publicKey);
 
-    const [delegation] = PublicKey.findProgramAddressSync(
-      [
-        Buffer.from("authorize"),
-        master.publicKey.toBuffer(),
-        representative.publicKey.toBuffer(),
-      ],
-      program.programId
-    );
+    const delegation = delegationOf(master.publicKey, representative.publicKey);
 
-    await program.methods
-      .initializeDelegate()
-      .accounts({
-        master: master.publicKey,
-        representative: representative.publicKey,
-        delegation,
-        systemProgram: SystemProgram.programId,
-      })
-      .signers([master])
-      .rpc();
+    await initializeDelegate(master, representative.publicKey);
 
-    assert.deepEqual(await program.account.delegation.fetch(delegation), {
+    assert.deepEqual(await fetchDelegation(delegation), {
       master: master.publicKey,
       representative: representative.publicKey,
       authorised: false,
+      permissions: PERMISSION_ALL.toString(),
     });
 
-    await program.methods
-      .confirmDelegate()
-      .accounts({
-        representative: representative.publicKey,
-        delegation,
-        systemProgram: SystemProgram.programId,
-      })
-      .signers([representative])
-      .rpc();
+    await confirmDelegate(representative, delegation);
 
-    assert.deepEqual(await program.account.delegation.fetch(delegation), {
+    assert.deepEqual(await fetchDelegation(delegation), {
       master: master.publicKey,
       representative: representative.publicKey,
       authorised: true,
+      permissions: PERMISSION_ALL.toString(),
     });
 
     const [counterAddress] = PublicKey.findProgramAddressSync(
@@ -264,10 +321,7 @@ describe("delegation-manager", () => {
         .signers([representative])
         .rpc();
     } catch (error) {
-      assert.ok(
-        error.logs[2].includes("Missing Delegation Account"),
-        "Wrong error"
-      );
+      assert.equal(error.error.errorCode.code, "MissingDelegationAccount");
     }
 
     await example.methods
@@ -280,6 +334,11 @@ describe("delegation-manager", () => {
       })
       .remainingAccounts([
         { pubkey: delegation, isSigner: false, isWritable: false },
+        {
+          pubkey: masterSettingsOf(master.publicKey),
+          isSigner: false,
+          isWritable: false,
+        },
       ])
       .signers([representative])
       .rpc();
@@ -295,12 +354,17 @@ describe("delegation-manager", () => {
         })
         .remainingAccounts([
           { pubkey: delegation, isSigner: false, isWritable: false },
+          {
+            pubkey: masterSettingsOf(master.publicKey),
+            isSigner: false,
+            isWritable: false,
+          },
         ])
         .signers([hacker])
         .rpc();
     } catch (error) {
       assert.ok(
-        error.errorLogs[0].includes("RequireKeysEqViolated"),
+        error.errorLogs[0].includes("WrongDelegationPda"),
         "Wrong error"
       );
     }
@@ -310,4 +374,1931 @@ describe("delegation-manager", () => {
       2
     );
   });
+
+  it("Assert authorized for programs without the check functions", async () => {
+    const { master, representative, delegation } =
+      await createConfirmedDelegation();
+    const hacker = Keypair.generate();
+
+    const assertAuthorized = (representative: PublicKey) =>
+      program.methods
+        .assertAuthorized(null, example.programId, PERMISSION_CLAIM)
+        .accounts({
+          master: master.publicKey,
+          representative,
+          delegation,
+          masterSettings: masterSettingsOf(master.publicKey),
+        })
+        .rpc();
+
+    await assertAuthorized(representative.publicKey);
+
+    try {
+      await assertAuthorized(hacker.publicKey);
+      assert(false);
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "WrongDelegationPda");
+    }
+
+    // the master is always authorised to act for itself
+    await assertAuthorized(master.publicKey);
+  });
+
+  it("Bad delegation accounts are rejected with typed errors", async () => {
+    const { master, representative, delegation } =
+      await createConfirmedDelegation();
+    const claimer = Keypair.generate();
+    const claimDelegation = delegationOf(master.publicKey, claimer.publicKey);
+    await initializeDelegate(
+      master,
+      claimer.publicKey,
+      terms({ permissions: PERMISSION_CLAIM })
+    );
+    await confirmDelegate(claimer, claimDelegation);
+
+    const [counterAddress] = PublicKey.findProgramAddressSync(
+      [Buffer.from("counter-state")],
+      example.programId
+    );
+
+    // the example program reads the delegation and master settings accounts
+    const incrementCounter = (delegationAccounts: PublicKey[]) =>
+      example.methods
+        .incrementCounter()
+        .accounts({
+          counter: counterAddress,
+          payer: representative.publicKey,
+          authority: master.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .remainingAccounts(
+          delegationAccounts.map((pubkey) => ({
+            pubkey,
+            isSigner: false,
+            isWritable: false,
+          }))
+        )
+        .signers([representative])
+        .rpc();
+    const masterSettings = masterSettingsOf(master.publicKey);
+
+    try {
+      await incrementCounter([]);
+      assert(false);
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "MissingDelegationAccount");
+    }
+
+    try {
+      await incrementCounter([counterAddress, masterSettings]);
+      assert(false);
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "InvalidDelegationAccount");
+    }
+
+    // a valid delegation of the master doesn't vouch for another representative
+    try {
+      await incrementCounter([claimDelegation, masterSettings]);
+      assert(false);
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "WrongDelegationPda");
+    }
+
+    await incrementCounter([delegation, masterSettings]);
+  });
+
+  it("Example program rejects delegations without its scope", async () => {
+    const { master, representative: claimer, delegation } =
+      await createConfirmedDelegation(terms({ permissions: PERMISSION_CLAIM }));
+
+    const [counterAddress] = PublicKey.findProgramAddressSync(
+      [Buffer.from("counter-state")],
+      example.programId
+    );
+
+    try {
+      await example.methods
+        .incrementCounter()
+        .accounts({
+          counter: counterAddress,
+          payer: claimer.publicKey,
+          authority: master.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .remainingAccounts([
+          { pubkey: delegation, isSigner: false, isWritable: false },
+          {
+            pubkey: masterSettingsOf(master.publicKey),
+            isSigner: false,
+            isWritable: false,
+          },
+        ])
+        .signers([claimer])
+        .rpc();
+      assert(false);
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "MissingPermission");
+    }
+  });
+
+  it("Initialize with scoped permissions", async () => {
+    const master = Keypair.generate();
+    const representative = Keypair.generate();
+
+    await airdrop(master.publicKey);
+
+    const delegation = delegationOf(master.publicKey, representative.publicKey);
+
+    try {
+      await initializeDelegate(
+        master,
+        representative.publicKey,
+        terms({ permissions: new anchor.BN(0) })
+      );
+      assert(false);
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "EmptyPermissions");
+    }
+
+    await initializeDelegate(
+      master,
+      representative.publicKey,
+      terms({ permissions: PERMISSION_CLAIM })
+    );
+
+    assert.deepEqual(await fetchDelegation(delegation), {
+      master: master.publicKey,
+      representative: representative.publicKey,
+      authorised: false,
+      permissions: PERMISSION_CLAIM.toString(),
+    });
+  });
+
+  it("Delegation outside of its validity window", async () => {
+    const master = Keypair.generate();
+    const representative = Keypair.generate();
+
+    await airdrop(master.publicKey);
+
+    const delegation = delegationOf(master.publicKey, representative.publicKey);
+    const now = Math.floor(Date.now() / 1000);
+
+    try {
+      await initializeDelegate(
+        master,
+        representative.publicKey,
+        terms({ validUntil: new anchor.BN(now - 3600) })
+      );
+      assert(false);
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "InvalidValidityWindow");
+    }
+
+    await initializeDelegate(
+      master,
+      representative.publicKey,
+      terms({
+        validFrom: new anchor.BN(now + 3600),
+        validUntil: new anchor.BN(now + 7200),
+      })
+    );
+
+    const account = await program.account.delegation.fetch(delegation);
+    assert.equal(account.validFrom.toNumber(), now + 3600);
+    assert.equal(account.validUntil.toNumber(), now + 7200);
+
+    await confirmDelegate(representative, delegation);
+
+    try {
+      await program.methods
+        .assertAuthorized(null, null, new anchor.BN(0))
+        .accounts({
+          master: master.publicKey,
+          representative: representative.publicKey,
+          delegation,
+          masterSettings: masterSettingsOf(master.publicKey),
+        })
+        .rpc();
+      assert(false);
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "NotYetActive");
+    }
+
+    const shortLived = Keypair.generate();
+    const expiringDelegation = delegationOf(
+      master.publicKey,
+      shortLived.publicKey
+    );
+    const validUntil = (await clockTime()) + 5;
+
+    await initializeDelegate(
+      master,
+      shortLived.publicKey,
+      terms({ validUntil: new anchor.BN(validUntil) })
+    );
+    await confirmDelegate(shortLived, expiringDelegation);
+
+    await waitForClock(validUntil);
+    try {
+      await program.methods
+        .assertAuthorized(null, null, new anchor.BN(0))
+        .accounts({
+          master: master.publicKey,
+          representative: shortLived.publicKey,
+          delegation: expiringDelegation,
+          masterSettings: masterSettingsOf(master.publicKey),
+        })
+        .rpc();
+      assert(false);
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "Expired");
+    }
+  });
+
+  it("Restrict delegation to allowed programs", async () => {
+    const master = Keypair.generate();
+    const representative = Keypair.generate();
+
+    await airdrop(master.publicKey);
+
+    const delegation = delegationOf(master.publicKey, representative.publicKey);
+
+    await initializeDelegate(
+      master,
+      representative.publicKey,
+      terms({ allowedPrograms: [example.programId] })
+    );
+
+    assert.deepEqual(
+      (await program.account.delegation.fetch(delegation)).allowedPrograms,
+      [example.programId]
+    );
+
+    try {
+      await program.methods
+        .setAllowedPrograms(
+          Array.from({ length: 9 }, () => Keypair.generate().publicKey)
+        )
+        .accounts({ master: master.publicKey, delegation })
+        .signers([master])
+        .rpc();
+      assert(false);
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "TooManyAllowedPrograms");
+    }
+
+    try {
+      await program.methods
+        .setAllowedPrograms([])
+        .accounts({ master: representative.publicKey, delegation })
+        .signers([representative])
+        .rpc();
+      assert(false);
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "WrongMaster");
+    }
+
+    await confirmDelegate(representative, delegation);
+
+    const useDelegate = (callingProgram: PublicKey) =>
+      program.methods
+        .useDelegate(null, callingProgram, PERMISSION_CLAIM)
+        .accounts({
+          master: master.publicKey,
+          representative: representative.publicKey,
+          delegation,
+          masterSettings: masterSettingsOf(master.publicKey),
+          masterIndex: masterIndexOf(master.publicKey),
+          representativeIndex: representativeIndexOf(representative.publicKey),
+        })
+        .signers([representative])
+        .rpc();
+
+    await useDelegate(example.programId);
+
+    try {
+      await useDelegate(SystemProgram.programId);
+      assert(false);
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "ProgramNotAllowed");
+    }
+
+    await program.methods
+      .setAllowedPrograms([])
+      .accounts({ master: master.publicKey, delegation })
+      .signers([master])
+      .rpc();
+
+    assert.deepEqual(
+      (await program.account.delegation.fetch(delegation)).allowedPrograms,
+      []
+    );
+  });
+
+  it("Namespaced delegations to the same representative", async () => {
+    const master = Keypair.generate();
+    const representative = Keypair.generate();
+    const namespaces = [Keypair.generate().publicKey, example.programId];
+
+    await airdrop(master.publicKey);
+
+    const namespacedDelegationOf = (namespace: PublicKey) =>
+      PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("authorize"),
+          master.publicKey.toBuffer(),
+          representative.publicKey.toBuffer(),
+          namespace.toBuffer(),
+        ],
+        program.programId
+      )[0];
+
+    for (const [index, namespace] of namespaces.entries()) {
+      const delegation = namespacedDelegationOf(namespace);
+      const permissions = new anchor.BN(1 << index);
+
+      await program.methods
+        .initializeNamespacedDelegate(
+          namespace,
+          terms({ permissions }),
+          metadata()
+        )
+        .accounts({
+          master: master.publicKey,
+          payer: master.publicKey,
+          representative: representative.publicKey,
+          delegation,
+          masterIndex: masterIndexOf(master.publicKey),
+          representativeIndex: representativeIndexOf(representative.publicKey),
+          masterSettings: masterSettingsOf(master.publicKey),
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([master])
+        .rpc();
+
+      const account = await program.account.delegation.fetch(delegation);
+      assert.ok(account.namespace.equals(namespace));
+      assert.equal(account.permissions.toString(), permissions.toString());
+    }
+
+    const delegation = namespacedDelegationOf(namespaces[0]);
+    await confirmDelegate(representative, delegation);
+
+    const assertAuthorized = (namespace: PublicKey | null) =>
+      program.methods
+        .assertAuthorized(namespace, null, new anchor.BN(0))
+        .accounts({
+          master: master.publicKey,
+          representative: representative.publicKey,
+          delegation,
+          masterSettings: masterSettingsOf(master.publicKey),
+        })
+        .rpc();
+
+    await assertAuthorized(namespaces[0]);
+
+    for (const namespace of [namespaces[1], null]) {
+      try {
+        await assertAuthorized(namespace);
+        assert(false);
+      } catch (error) {
+        assert.equal(error.error.errorCode.code, "WrongNamespace");
+      }
+    }
+  });
+
+  it("Allowance caps the amount spent by the representative", async () => {
+    const { master, representative, delegation } =
+      await createConfirmedDelegation();
+    const mint = Keypair.generate().publicKey;
+
+    const [allowance] = PublicKey.findProgramAddressSync(
+      [Buffer.from("allowance"), delegation.toBuffer(), mint.toBuffer()],
+      program.programId
+    );
+
+    const setAllowance = () =>
+      program.methods
+        .setAllowance(mint, new anchor.BN(100))
+        .accounts({
+          master: master.publicKey,
+          delegation,
+          allowance,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([master])
+        .rpc();
+
+    await setAllowance();
+
+    await program.methods
+      .consumeAllowance(new anchor.BN(60))
+      .accounts({
+        representative: representative.publicKey,
+        delegation,
+        allowance,
+        masterSettings: masterSettingsOf(master.publicKey),
+      })
+      .signers([representative])
+      .rpc();
+
+    try {
+      await program.methods
+        .consumeAllowance(new anchor.BN(41))
+        .accounts({
+          representative: representative.publicKey,
+          delegation,
+          allowance,
+          masterSettings: masterSettingsOf(master.publicKey),
+        })
+        .signers([representative])
+        .rpc();
+      assert(false);
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "AllowanceExceeded");
+    }
+
+    const account = await program.account.allowance.fetch(allowance);
+    assert.equal(account.cap.toNumber(), 100);
+    assert.equal(account.spent.toNumber(), 60);
+
+    await cancelDelegate(master, master.publicKey, representative.publicKey);
+    await initializeDelegate(master, representative.publicKey);
+    await confirmDelegate(representative, delegation);
+
+    const consumeAllowance = (amount: number) =>
+      program.methods
+        .consumeAllowance(new anchor.BN(amount))
+        .accounts({
+          representative: representative.publicKey,
+          delegation,
+          allowance,
+          masterSettings: masterSettingsOf(master.publicKey),
+        })
+        .signers([representative])
+        .rpc();
+
+    try {
+      await consumeAllowance(1);
+      assert(false);
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "StaleAllowance");
+    }
+
+    await setAllowance();
+    await consumeAllowance(100);
+
+    await program.methods
+      .closeAllowance()
+      .accounts({ master: master.publicKey, allowance })
+      .signers([master])
+      .rpc();
+
+    assert.isNull(await connection.getAccountInfo(allowance));
+  });
+
+  it("Sub-delegations can't spend allowances", async () => {
+    const {
+      master,
+      representative: operator,
+      delegation: parentDelegation,
+    } = await createConfirmedDelegation();
+    const bot = Keypair.generate();
+    const mint = Keypair.generate().publicKey;
+
+    await airdrop(operator.publicKey);
+
+    const delegation = delegationOf(parentDelegation, bot.publicKey);
+    await program.methods
+      .initializeSubDelegate(terms())
+      .accounts({
+        delegator: operator.publicKey,
+        payer: operator.publicKey,
+        parentDelegation,
+        representative: bot.publicKey,
+        delegation,
+        masterIndex: masterIndexOf(operator.publicKey),
+        representativeIndex: representativeIndexOf(bot.publicKey),
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([operator])
+      .rpc();
+    await confirmDelegate(bot, delegation);
+
+    const [allowance] = PublicKey.findProgramAddressSync(
+      [Buffer.from("allowance"), delegation.toBuffer(), mint.toBuffer()],
+      program.programId
+    );
+
+    await program.methods
+      .setAllowance(mint, new anchor.BN(100))
+      .accounts({
+        master: operator.publicKey,
+        delegation,
+        allowance,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([operator])
+      .rpc();
+
+    // the parent no longer authorizes the operator, nor should the bot spend
+    await cancelDelegate(master, master.publicKey, operator.publicKey);
+
+    try {
+      await program.methods
+        .consumeAllowance(new anchor.BN(1))
+        .accounts({
+          representative: bot.publicKey,
+          delegation,
+          allowance,
+          masterSettings: masterSettingsOf(operator.publicKey),
+        })
+        .signers([bot])
+        .rpc();
+      assert(false);
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "SubDelegation");
+    }
+  });
+
+  it("Usage-limited delegation is cancelled once used up", async () => {
+    const { master, representative, delegation } =
+      await createConfirmedDelegation(terms({ maxUses: 2 }));
+
+    const useDelegate = () =>
+      program.methods
+        .useDelegate(null, null, PERMISSION_CLAIM)
+        .accounts({
+          master: master.publicKey,
+          representative: representative.publicKey,
+          delegation,
+          masterSettings: masterSettingsOf(master.publicKey),
+          masterIndex: masterIndexOf(master.publicKey),
+          representativeIndex: representativeIndexOf(representative.publicKey),
+        })
+        .signers([representative])
+        .rpc();
+
+    await useDelegate();
+    assert.equal((await program.account.delegation.fetch(delegation)).uses, 1);
+
+    let listener: number;
+    const cancelled = new Promise<any>((resolve) => {
+      listener = program.addEventListener("DelegationCancelled", resolve);
+    });
+
+    await useDelegate();
+
+    const event = await cancelled;
+    await program.removeEventListener(listener);
+
+    assert.ok(event.delegation.equals(delegation));
+    assert.ok(event.cancelledBy.equals(representative.publicKey));
+    assert.isNull(await connection.getAccountInfo(delegation));
+
+    const masterIndex = await program.account.masterIndex.fetch(
+      masterIndexOf(master.publicKey)
+    );
+    assert.equal(masterIndex.delegations.length, 0);
+    const representativeIndex = await program.account.representativeIndex.fetch(
+      representativeIndexOf(representative.publicKey)
+    );
+    assert.equal(representativeIndex.delegations.length, 0);
+  });
+
+  it("Sub-delegate with narrower permissions", async () => {
+    const master = Keypair.generate();
+    const operator = Keypair.generate();
+    const bot = Keypair.generate();
+
+    await airdrop(master.publicKey);
+    await airdrop(operator.publicKey);
+    await airdrop(bot.publicKey);
+
+    const parentDelegation = delegationOf(master.publicKey, operator.publicKey);
+    const delegation = delegationOf(parentDelegation, bot.publicKey);
+
+    const initializeParentDelegate = () =>
+      initializeDelegate(
+        master,
+        operator.publicKey,
+        terms({ permissions: new anchor.BN(0b11) })
+      );
+
+    await initializeParentDelegate();
+
+    const initializeSubDelegate = (permissions: anchor.BN) =>
+      program.methods
+        .initializeSubDelegate(terms({ permissions }))
+        .accounts({
+          delegator: operator.publicKey,
+          payer: operator.publicKey,
+          parentDelegation,
+          representative: bot.publicKey,
+          delegation,
+          masterIndex: masterIndexOf(operator.publicKey),
+          representativeIndex: representativeIndexOf(bot.publicKey),
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([operator])
+        .rpc();
+
+    try {
+      await initializeSubDelegate(PERMISSION_CLAIM);
+      assert(false);
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "NotAuthorized");
+    }
+
+    await confirmDelegate(operator, parentDelegation);
+
+    try {
+      await initializeSubDelegate(new anchor.BN(0b111));
+      assert(false);
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "MissingPermission");
+    }
+
+    await initializeSubDelegate(PERMISSION_CLAIM);
+
+    const account = await program.account.delegation.fetch(delegation);
+    assert.ok(account.master.equals(operator.publicKey));
+    assert.ok(account.representative.equals(bot.publicKey));
+    assert.ok(account.parent.equals(parentDelegation));
+    assert.equal(account.depth, 1);
+
+    await confirmDelegate(bot, delegation);
+
+    const assertAuthorizedChain = (maxDepth: number) =>
+      program.methods
+        .assertAuthorizedChain(null, null, PERMISSION_CLAIM, maxDepth)
+        .accounts({
+          master: master.publicKey,
+          representative: bot.publicKey,
+          masterSettings: masterSettingsOf(master.publicKey),
+        })
+        .remainingAccounts(
+          [parentDelegation, delegation].map((pubkey) => ({
+            pubkey,
+            isSigner: false,
+            isWritable: false,
+          }))
+        )
+        .rpc();
+
+    await assertAuthorizedChain(1);
+
+    try {
+      await assertAuthorizedChain(0);
+      assert(false);
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "DelegationChainTooDeep");
+    }
+
+    await cancelDelegate(master, master.publicKey, operator.publicKey);
+
+    // re-creating the parent doesn't revive the sub-delegation of the cancelled one
+    await initializeParentDelegate();
+    await confirmDelegate(operator, parentDelegation);
+
+    try {
+      await assertAuthorizedChain(1);
+      assert(false);
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "InvalidDelegationChain");
+    }
+  });
+
+  it("Request, approve, cancel by representative", async () => {
+    const master = Keypair.generate();
+    const representative = Keypair.generate();
+
+    await airdrop(master.publicKey);
+    await airdrop(representative.publicKey);
+
+    const delegation = delegationOf(master.publicKey, representative.publicKey);
+
+    await program.methods
+      .requestDelegation(terms({ permissions: PERMISSION_CLAIM }))
+      .accounts({
+        representative: representative.publicKey,
+        master: master.publicKey,
+        delegation,
+        representativeIndex: representativeIndexOf(representative.publicKey),
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([representative])
+      .rpc();
+
+    assert.deepEqual(await fetchDelegation(delegation), {
+      master: master.publicKey,
+      representative: representative.publicKey,
+      authorised: false,
+      permissions: PERMISSION_CLAIM.toString(),
+    });
+
+    try {
+      await confirmDelegate(representative, delegation);
+      assert(false);
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "DelegationRequested");
+    }
+
+    // requests aren't listed by the master until approved
+    assert.isNull(
+      await connection.getAccountInfo(masterIndexOf(master.publicKey))
+    );
+
+    await program.methods
+      .approveDelegationRequest()
+      .accounts({
+        master: master.publicKey,
+        delegation,
+        masterIndex: masterIndexOf(master.publicKey),
+        representativeIndex: representativeIndexOf(representative.publicKey),
+        masterSettings: masterSettingsOf(master.publicKey),
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([master])
+      .rpc();
+
+    assert.isTrue((await program.account.delegation.fetch(delegation)).authorised);
+    const masterIndex = await program.account.masterIndex.fetch(
+      masterIndexOf(master.publicKey)
+    );
+    assert.ok(masterIndex.delegations[0].delegation.equals(delegation));
+
+    const balance = await connection.getBalance(representative.publicKey);
+    const rent = await connection.getBalance(delegation);
+
+    await program.methods
+      .cancelDelegate()
+      .accounts({
+        delegation,
+        rentRecipient: representative.publicKey,
+        masterIndex: masterIndexOf(master.publicKey),
+        representativeIndex: representativeIndexOf(representative.publicKey),
+        systemProgram: SystemProgram.programId,
+      })
+      .remainingAccounts([
+        { pubkey: master.publicKey, isSigner: false, isWritable: false },
+        {
+          pubkey: representative.publicKey,
+          isSigner: true,
+          isWritable: true,
+        },
+      ])
+      .signers([representative])
+      .rpc();
+
+    assert.isNull(await connection.getAccountInfo(delegation));
+    assert.isAbove(
+      await connection.getBalance(representative.publicKey),
+      balance + rent / 2
+    );
+  });
+
+  it("Decline by representative", async () => {
+    const master = Keypair.generate();
+    const representative = Keypair.generate();
+
+    await airdrop(master.publicKey);
+
+    const delegation = delegationOf(master.publicKey, representative.publicKey);
+
+    await initializeDelegate(master, representative.publicKey);
+
+    let listener: number;
+    const declined = new Promise<any>((resolve) => {
+      listener = program.addEventListener("DelegationDeclined", resolve);
+    });
+
+    await program.methods
+      .declineDelegate()
+      .accounts({
+        representative: representative.publicKey,
+        master: master.publicKey,
+        delegation,
+        rentRecipient: master.publicKey,
+        masterIndex: masterIndexOf(master.publicKey),
+        representativeIndex: representativeIndexOf(representative.publicKey),
+      })
+      .signers([representative])
+      .rpc();
+
+    const event = await declined;
+    await program.removeEventListener(listener);
+
+    assert.ok(event.master.equals(master.publicKey));
+    assert.ok(event.representative.equals(representative.publicKey));
+    assert.ok(event.delegation.equals(delegation));
+    assert.isNull(await connection.getAccountInfo(delegation));
+  });
+
+  it("Emit lifecycle events", async () => {
+    const master = Keypair.generate();
+    const representative = Keypair.generate();
+
+    await airdrop(master.publicKey);
+
+    const delegation = delegationOf(master.publicKey, representative.publicKey);
+
+    const nextEvent = async (name: string, send: () => Promise<string>) => {
+      let listener: number;
+      const emitted = new Promise<any>((resolve) => {
+        listener = program.addEventListener(name, resolve);
+      });
+      await send();
+      const event = await emitted;
+      await program.removeEventListener(listener);
+      assert.ok(event.master.equals(master.publicKey));
+      assert.ok(event.representative.equals(representative.publicKey));
+      assert.ok(event.delegation.equals(delegation));
+      return event;
+    };
+
+    const created = await nextEvent("DelegationCreated", () =>
+      initializeDelegate(master, representative.publicKey)
+    );
+    assert.isFalse(created.requested);
+
+    const confirmed = await nextEvent("DelegationConfirmed", () =>
+      confirmDelegate(representative, delegation)
+    );
+    assert.ok(confirmed.confirmedBy.equals(representative.publicKey));
+
+    const cancelled = await nextEvent("DelegationCancelled", () =>
+      cancelDelegate(master, master.publicKey, representative.publicKey)
+    );
+    assert.ok(cancelled.cancelledBy.equals(master.publicKey));
+  });
+
+  it("Only legacy delegations can be migrated", async () => {
+    const master = Keypair.generate();
+    const representative = Keypair.generate();
+
+    await airdrop(master.publicKey);
+
+    const delegation = delegationOf(master.publicKey, representative.publicKey);
+
+    await initializeDelegate(master, representative.publicKey);
+
+    assert.equal(
+      (await program.account.delegation.fetch(delegation)).version,
+      2
+    );
+
+    try {
+      await program.methods
+        .migrateDelegation()
+        .accounts({
+          payer: master.publicKey,
+          delegation,
+          masterIndex: masterIndexOf(master.publicKey),
+          representativeIndex: representativeIndexOf(representative.publicKey),
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([master])
+        .rpc();
+      assert(false);
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "AlreadyMigrated");
+    }
+  });
+
+  it("Legacy delegations are honoured, migrated and cancelled", async () => {
+    // preloaded in the legacy layout, the migrated one with their roles swapped
+    const master = fixtureKeypair("legacy-master");
+    const representative = fixtureKeypair("legacy-representative");
+    const delegation = delegationOf(master.publicKey, representative.publicKey);
+    const swapped = delegationOf(representative.publicKey, master.publicKey);
+    const masterSettings = masterSettingsOf(master.publicKey);
+
+    await airdrop(master.publicKey);
+    await airdrop(representative.publicKey);
+
+    assert.equal((await connection.getAccountInfo(delegation)).data.length, 73);
+
+    await program.methods
+      .assertAuthorized(null, example.programId, PERMISSION_CLAIM)
+      .accounts({
+        master: master.publicKey,
+        representative: representative.publicKey,
+        delegation,
+        masterSettings,
+      })
+      .rpc();
+
+    const [counterAddress] = PublicKey.findProgramAddressSync(
+      [Buffer.from("counter-state")],
+      example.programId
+    );
+    await example.methods
+      .incrementCounter()
+      .accounts({
+        counter: counterAddress,
+        payer: representative.publicKey,
+        authority: master.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .remainingAccounts(
+        [delegation, masterSettings].map((pubkey) => ({
+          pubkey,
+          isSigner: false,
+          isWritable: false,
+        }))
+      )
+      .signers([representative])
+      .rpc();
+
+    await program.methods
+      .migrateDelegation()
+      .accounts({
+        payer: master.publicKey,
+        delegation: swapped,
+        masterIndex: masterIndexOf(representative.publicKey),
+        representativeIndex: representativeIndexOf(master.publicKey),
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([master])
+      .rpc();
+
+    const migrated = await program.account.delegation.fetch(swapped);
+    assert.equal(migrated.version, 2);
+    assert.ok(migrated.master.equals(representative.publicKey));
+    assert.ok(migrated.representative.equals(master.publicKey));
+    assert.isTrue(migrated.authorised);
+    assert.equal(migrated.permissions.toString(), PERMISSION_ALL.toString());
+    // legacy delegations didn't record it, the slot of the migration stands in
+    assert.isAbove(migrated.createdSlot.toNumber(), 0);
+    const masterIndex = await program.account.masterIndex.fetch(
+      masterIndexOf(representative.publicKey)
+    );
+    assert.ok(masterIndex.delegations[0].delegation.equals(swapped));
+
+    // cancelled without being migrated, the rent going back to the master
+    await cancelDelegate(master, master.publicKey, representative.publicKey);
+
+    assert.isNull(await connection.getAccountInfo(delegation));
+  });
+
+  it("Master index lists the delegations of a master", async () => {
+    const master = Keypair.generate();
+    const representatives = [Keypair.generate(), Keypair.generate()];
+
+    await airdrop(master.publicKey);
+
+    const delegations = [];
+    for (const representative of representatives) {
+      delegations.push(
+        delegationOf(master.publicKey, representative.publicKey)
+      );
+      await initializeDelegate(master, representative.publicKey);
+    }
+
+    const fetchIndex = async () =>
+      (
+        await program.account.masterIndex.fetch(masterIndexOf(master.publicKey))
+      ).delegations.map((entry) => entry.delegation.toBase58());
+
+    assert.deepEqual(
+      await fetchIndex(),
+      delegations.map((delegation) => delegation.toBase58())
+    );
+
+    await cancelDelegate(
+      master,
+      master.publicKey,
+      representatives[0].publicKey
+    );
+
+    assert.deepEqual(await fetchIndex(), [delegations[1].toBase58()]);
+  });
+
+  it("Delegations beyond a full master index aren't listed", async () => {
+    // its MasterIndex is preloaded with MAX_INDEX_ENTRIES delegations
+    const master = fixtureKeypair("full-index-master");
+    const representative = Keypair.generate();
+
+    await airdrop(master.publicKey);
+
+    const delegation = delegationOf(master.publicKey, representative.publicKey);
+    const masterIndex = masterIndexOf(master.publicKey);
+    const space = (await connection.getAccountInfo(masterIndex)).data.length;
+
+    await initializeDelegate(master, representative.publicKey);
+
+    const { delegations } = await program.account.masterIndex.fetch(
+      masterIndex
+    );
+    assert.equal(delegations.length, 1024);
+    assert.isFalse(
+      delegations.some((entry) => entry.delegation.equals(delegation))
+    );
+    assert.equal(
+      (await connection.getAccountInfo(masterIndex)).data.length,
+      space
+    );
+
+    await cancelDelegate(master, master.publicKey, representative.publicKey);
+
+    assert.isNull(await connection.getAccountInfo(delegation));
+    assert.equal(
+      (await program.account.masterIndex.fetch(masterIndex)).delegations.length,
+      1024
+    );
+  });
+
+  it("Representative index lists whom a wallet represents", async () => {
+    const master = Keypair.generate();
+    const representative = Keypair.generate();
+
+    await airdrop(master.publicKey);
+
+    const delegation = delegationOf(master.publicKey, representative.publicKey);
+
+    await initializeDelegate(master, representative.publicKey);
+
+    const fetchIndex = async () =>
+      (
+        await program.account.representativeIndex.fetch(
+          representativeIndexOf(representative.publicKey)
+        )
+      ).delegations;
+
+    let [entry] = await fetchIndex();
+    assert.ok(entry.master.equals(master.publicKey));
+    assert.ok(entry.delegation.equals(delegation));
+    assert.isFalse(entry.confirmed);
+
+    await confirmDelegate(representative, delegation);
+
+    [entry] = await fetchIndex();
+    assert.isTrue(entry.confirmed);
+  });
+
+  it("Representative index moves its last entry to removed ones", async () => {
+    const masters = [1, 2, 3].map(() => Keypair.generate());
+    const representative = Keypair.generate();
+
+    const delegations = [];
+    for (const master of masters) {
+      await airdrop(master.publicKey);
+      await initializeDelegate(master, representative.publicKey);
+      delegations.push(
+        delegationOf(master.publicKey, representative.publicKey)
+      );
+    }
+
+    await cancelDelegate(
+      masters[0],
+      masters[0].publicKey,
+      representative.publicKey
+    );
+
+    const index = await program.account.representativeIndex.fetch(
+      representativeIndexOf(representative.publicKey)
+    );
+    assert.deepEqual(
+      index.delegations.map((entry) => entry.delegation.toBase58()),
+      [delegations[2].toBase58(), delegations[1].toBase58()]
+    );
+    assert.ok(index.delegations[0].master.equals(masters[2].publicKey));
+  });
+
+  it("Pause, resume and revoke all delegations of a master", async () => {
+    const { master, representative, delegation } =
+      await createConfirmedDelegation();
+
+    const useDelegate = () =>
+      program.methods
+        .useDelegate(null, null, PERMISSION_CLAIM)
+        .accounts({
+          master: master.publicKey,
+          representative: representative.publicKey,
+          delegation,
+          masterSettings: masterSettingsOf(master.publicKey),
+          masterIndex: masterIndexOf(master.publicKey),
+          representativeIndex: representativeIndexOf(representative.publicKey),
+        })
+        .signers([representative])
+        .rpc();
+
+    const updateMasterSettings = (method: string) =>
+      program.methods[method]()
+        .accounts({
+          master: master.publicKey,
+          masterSettings: masterSettingsOf(master.publicKey),
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([master])
+        .rpc();
+
+    await useDelegate();
+
+    await updateMasterSettings("pauseAll");
+    try {
+      await useDelegate();
+      assert(false);
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "Paused");
+    }
+
+    await updateMasterSettings("resumeAll");
+    await useDelegate();
+
+    await updateMasterSettings("revokeAll");
+    try {
+      await useDelegate();
+      assert(false);
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "Revoked");
+    }
+
+    await cancelDelegate(master, master.publicKey, representative.publicKey);
+
+    // delegations created after revoke_all take the new revocation epoch
+    await initializeDelegate(master, representative.publicKey);
+    await confirmDelegate(representative, delegation);
+    const account = await program.account.delegation.fetch(delegation);
+    assert.equal(account.revocationEpoch.toNumber(), 1);
+    await useDelegate();
+  });
+
+  it("Transfer the delegations of a master to a new wallet", async () => {
+    const { master, representative, delegation } =
+      await createConfirmedDelegation();
+    const newMaster = Keypair.generate();
+
+    await airdrop(newMaster.publicKey);
+
+    const [masterTransfer] = PublicKey.findProgramAddressSync(
+      [Buffer.from("master_transfer"), master.publicKey.toBuffer()],
+      program.programId
+    );
+
+    await program.methods
+      .proposeMasterTransfer(newMaster.publicKey)
+      .accounts({
+        master: master.publicKey,
+        masterTransfer,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([master])
+      .rpc();
+
+    await program.methods
+      .acceptMasterTransfer()
+      .accounts({
+        newMaster: newMaster.publicKey,
+        master: master.publicKey,
+        masterTransfer,
+        masterSettings: masterSettingsOf(master.publicKey),
+        newMasterSettings: masterSettingsOf(newMaster.publicKey),
+        masterIndex: masterIndexOf(master.publicKey),
+        newMasterIndex: masterIndexOf(newMaster.publicKey),
+        systemProgram: SystemProgram.programId,
+      })
+      .remainingAccounts([
+        { pubkey: delegation, isSigner: false, isWritable: true },
+        {
+          pubkey: representativeIndexOf(representative.publicKey),
+          isSigner: false,
+          isWritable: true,
+        },
+      ])
+      .signers([newMaster])
+      .rpc();
+
+    const delegationAccount = await program.account.delegation.fetch(
+      delegation
+    );
+    assert.equal(
+      delegationAccount.master.toBase58(),
+      newMaster.publicKey.toBase58()
+    );
+    assert.equal(
+      delegationAccount.originalMaster.toBase58(),
+      master.publicKey.toBase58()
+    );
+    assert.isTrue(delegationAccount.authorised);
+
+    const newMasterIndex = await program.account.masterIndex.fetch(
+      masterIndexOf(newMaster.publicKey)
+    );
+    assert.equal(
+      newMasterIndex.delegations[0].delegation.toBase58(),
+      delegation.toBase58()
+    );
+    const representativeIndex = await program.account.representativeIndex.fetch(
+      representativeIndexOf(representative.publicKey)
+    );
+    assert.equal(
+      representativeIndex.delegations[0].master.toBase58(),
+      newMaster.publicKey.toBase58()
+    );
+
+    await program.methods
+      .useDelegate(null, null, PERMISSION_CLAIM)
+      .accounts({
+        master: newMaster.publicKey,
+        representative: representative.publicKey,
+        delegation,
+        masterSettings: masterSettingsOf(newMaster.publicKey),
+        masterIndex: masterIndexOf(newMaster.publicKey),
+        representativeIndex: representativeIndexOf(representative.publicKey),
+      })
+      .signers([representative])
+      .rpc();
+
+    await program.methods
+      .closeMasterTransfer()
+      .accounts({ master: master.publicKey, masterTransfer })
+      .signers([master])
+      .rpc();
+  });
+
+  it("Transfer unassigns the role of the previous master", async () => {
+    const { master, representative, delegation } =
+      await createConfirmedDelegation(terms({ permissions: PERMISSION_CLAIM }));
+    const newMaster = Keypair.generate();
+    const PERMISSION_WITHDRAW = new anchor.BN(1 << 4);
+
+    await airdrop(newMaster.publicKey);
+
+    const role = roleOf(master.publicKey, "bot");
+    await program.methods
+      .createRole("bot", PERMISSION_CLAIM, [])
+      .accounts({
+        authority: master.publicKey,
+        role,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([master])
+      .rpc();
+    await program.methods
+      .assignRole(false)
+      .accounts({ master: master.publicKey, delegation, role })
+      .signers([master])
+      .rpc();
+
+    const [masterTransfer] = PublicKey.findProgramAddressSync(
+      [Buffer.from("master_transfer"), master.publicKey.toBuffer()],
+      program.programId
+    );
+    await program.methods
+      .proposeMasterTransfer(newMaster.publicKey)
+      .accounts({
+        master: master.publicKey,
+        masterTransfer,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([master])
+      .rpc();
+    await program.methods
+      .acceptMasterTransfer()
+      .accounts({
+        newMaster: newMaster.publicKey,
+        master: master.publicKey,
+        masterTransfer,
+        masterSettings: masterSettingsOf(master.publicKey),
+        newMasterSettings: masterSettingsOf(newMaster.publicKey),
+        masterIndex: masterIndexOf(master.publicKey),
+        newMasterIndex: masterIndexOf(newMaster.publicKey),
+        systemProgram: SystemProgram.programId,
+      })
+      .remainingAccounts([
+        { pubkey: delegation, isSigner: false, isWritable: true },
+        {
+          pubkey: representativeIndexOf(representative.publicKey),
+          isSigner: false,
+          isWritable: true,
+        },
+      ])
+      .signers([newMaster])
+      .rpc();
+
+    assert.isNull((await program.account.delegation.fetch(delegation)).role);
+
+    // the previous master still controls the role, but it no longer applies
+    await program.methods
+      .updateRole(PERMISSION_ALL, [])
+      .accounts({ authority: master.publicKey, role })
+      .signers([master])
+      .rpc();
+
+    try {
+      await program.methods
+        .assertAuthorized(null, null, PERMISSION_WITHDRAW)
+        .accounts({
+          master: newMaster.publicKey,
+          representative: representative.publicKey,
+          delegation,
+          masterSettings: masterSettingsOf(newMaster.publicKey),
+        })
+        .remainingAccounts([
+          { pubkey: role, isSigner: false, isWritable: false },
+        ])
+        .rpc();
+      assert(false);
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "MissingPermission");
+    }
+  });
+
+  it("Rotate the representative to a new key", async () => {
+    const { master, representative, delegation } =
+      await createConfirmedDelegation(terms({ permissions: PERMISSION_CLAIM }));
+    const newRepresentative = Keypair.generate();
+
+    await airdrop(representative.publicKey);
+
+    const newDelegation = delegationOf(
+      master.publicKey,
+      newRepresentative.publicKey
+    );
+
+    const rotate = () =>
+      program.methods
+        .rotateRepresentative()
+        .accounts({
+          representative: representative.publicKey,
+          newRepresentative: newRepresentative.publicKey,
+          master: master.publicKey,
+          delegation,
+          newDelegation,
+          masterIndex: masterIndexOf(master.publicKey),
+          representativeIndex: representativeIndexOf(representative.publicKey),
+          newRepresentativeIndex: representativeIndexOf(
+            newRepresentative.publicKey
+          ),
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([representative, newRepresentative])
+        .rpc();
+
+    try {
+      await rotate();
+      assert(false);
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "RotationNotAllowed");
+    }
+
+    await program.methods
+      .setRotationAllowed(true)
+      .accounts({ master: master.publicKey, delegation })
+      .signers([master])
+      .rpc();
+
+    await rotate();
+
+    assert.isNull(await program.account.delegation.fetchNullable(delegation));
+    assert.deepEqual(await fetchDelegation(newDelegation), {
+      master: master.publicKey,
+      representative: newRepresentative.publicKey,
+      authorised: true,
+      permissions: PERMISSION_CLAIM.toString(),
+    });
+
+    const representativeIndex = await program.account.representativeIndex.fetch(
+      representativeIndexOf(newRepresentative.publicKey)
+    );
+    assert.equal(
+      representativeIndex.delegations[0].delegation.toBase58(),
+      newDelegation.toBase58()
+    );
+    assert.isTrue(representativeIndex.delegations[0].confirmed);
+  });
+
+  it("Sponsor pays the rent and is refunded on cancel", async () => {
+    const master = Keypair.generate();
+    const sponsor = Keypair.generate();
+    const representative = Keypair.generate();
+
+    await airdrop(sponsor.publicKey);
+
+    const delegation = delegationOf(master.publicKey, representative.publicKey);
+
+    await program.methods
+      .initializeDelegate(terms(), metadata())
+      .accounts({
+        master: master.publicKey,
+        payer: sponsor.publicKey,
+        representative: representative.publicKey,
+        delegation,
+        masterIndex: masterIndexOf(master.publicKey),
+        representativeIndex: representativeIndexOf(representative.publicKey),
+        masterSettings: masterSettingsOf(master.publicKey),
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([master, sponsor])
+      .rpc();
+
+    assert.equal(await connection.getBalance(master.publicKey), 0);
+    assert.ok(
+      (await program.account.delegation.fetch(delegation)).rentRecipient.equals(
+        sponsor.publicKey
+      )
+    );
+
+    const cancelTo = (rentRecipient: PublicKey) =>
+      program.methods
+        .cancelDelegate()
+        .accounts({
+          delegation,
+          rentRecipient,
+          masterIndex: masterIndexOf(master.publicKey),
+          representativeIndex: representativeIndexOf(representative.publicKey),
+          systemProgram: SystemProgram.programId,
+        })
+        .remainingAccounts([
+          { pubkey: master.publicKey, isSigner: true, isWritable: false },
+          {
+            pubkey: representative.publicKey,
+            isSigner: false,
+            isWritable: false,
+          },
+        ])
+        .signers([master])
+        .rpc();
+
+    try {
+      await cancelTo(master.publicKey);
+      assert(false);
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "WrongRentRecipient");
+    }
+
+    const balance = await connection.getBalance(sponsor.publicKey);
+    await cancelTo(sponsor.publicKey);
+    assert.isAbove(await connection.getBalance(sponsor.publicKey), balance);
+    assert.equal(await connection.getBalance(master.publicKey), 0);
+  });
+
+  it("Label a delegation and update its metadata", async () => {
+    const master = Keypair.generate();
+    const representative = Keypair.generate();
+
+    await airdrop(master.publicKey);
+
+    const delegation = delegationOf(master.publicKey, representative.publicKey);
+
+    await initializeDelegate(
+      master,
+      representative.publicKey,
+      terms(),
+      metadata({ label: "trading bot" })
+    );
+
+    let account = await program.account.delegation.fetch(delegation);
+    assert.equal(account.label, "trading bot");
+    assert.equal(account.metadataUri, "");
+
+    await program.methods
+      .updateDelegationMetadata(
+        metadata({
+          label: "claim bot",
+          metadataUri: "https://example.com/claim-bot.json",
+        })
+      )
+      .accounts({ master: master.publicKey, delegation })
+      .signers([master])
+      .rpc();
+
+    account = await program.account.delegation.fetch(delegation);
+    assert.equal(account.label, "claim bot");
+    assert.equal(account.metadataUri, "https://example.com/claim-bot.json");
+
+    try {
+      await program.methods
+        .updateDelegationMetadata(metadata({ label: "x".repeat(33) }))
+        .accounts({ master: master.publicKey, delegation })
+        .signers([master])
+        .rpc();
+      assert(false);
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "LabelTooLong");
+    }
+  });
+
+  it("Confirm with the representative's off-chain signature", async () => {
+    const master = Keypair.generate();
+    const representative = Keypair.generate();
+    const hacker = Keypair.generate();
+
+    await airdrop(master.publicKey);
+
+    const delegation = delegationOf(master.publicKey, representative.publicKey);
+
+    await initializeDelegate(master, representative.publicKey);
+
+    const { createdSlot } = await program.account.delegation.fetch(delegation);
+    const message = Buffer.concat([
+      program.programId.toBuffer(),
+      delegation.toBuffer(),
+      master.publicKey.toBuffer(),
+      representative.publicKey.toBuffer(),
+      createdSlot.toArrayLike(Buffer, "le", 8),
+    ]);
+
+    const confirmDelegateWithSignature = (signer?: Keypair) =>
+      program.methods
+        .confirmDelegateWithSignature()
+        .accounts({
+          representative: representative.publicKey,
+          delegation,
+          representativeIndex: representativeIndexOf(representative.publicKey),
+          instructions: SYSVAR_INSTRUCTIONS_PUBKEY,
+        })
+        .preInstructions(
+          signer
+            ? [
+                Ed25519Program.createInstructionWithPrivateKey({
+                  privateKey: signer.secretKey,
+                  message,
+                }),
+              ]
+            : []
+        )
+        .rpc();
+
+    try {
+      await confirmDelegateWithSignature();
+      assert(false);
+    } catch (error) {
+      assert.equal(
+        error.error.errorCode.code,
+        "MissingSignatureVerification"
+      );
+    }
+
+    try {
+      await confirmDelegateWithSignature(hacker);
+      assert(false);
+    } catch (error) {
+      assert.equal(
+        error.error.errorCode.code,
+        "InvalidSignatureVerification"
+      );
+    }
+
+    await confirmDelegateWithSignature(representative);
+    assert.isTrue(
+      (await program.account.delegation.fetch(delegation)).authorised
+    );
+  });
+
+  it("Initialize delegations to several representatives at once", async () => {
+    const master = Keypair.generate();
+    const representatives = [0, 1, 2].map(() => Keypair.generate().publicKey);
+
+    await airdrop(master.publicKey);
+
+    const delegationTo = (representative: PublicKey) =>
+      delegationOf(master.publicKey, representative);
+
+    const initializeDelegatesBatch = (delegations: PublicKey[]) =>
+      program.methods
+        .initializeDelegatesBatch(
+          terms({ permissions: PERMISSION_CLAIM }),
+          metadata({ label: "bot fleet" })
+        )
+        .accounts({
+          master: master.publicKey,
+          payer: master.publicKey,
+          masterIndex: masterIndexOf(master.publicKey),
+          masterSettings: masterSettingsOf(master.publicKey),
+          systemProgram: SystemProgram.programId,
+        })
+        .remainingAccounts(
+          representatives.flatMap((representative, i) => [
+            { pubkey: representative, isSigner: false, isWritable: false },
+            { pubkey: delegations[i], isSigner: false, isWritable: true },
+            {
+              pubkey: representativeIndexOf(representative),
+              isSigner: false,
+              isWritable: true,
+            },
+          ])
+        )
+        .signers([master])
+        .rpc();
+
+    try {
+      await initializeDelegatesBatch(
+        [...representatives].reverse().map(delegationTo)
+      );
+      assert(false);
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "ConstraintSeeds");
+    }
+
+    await initializeDelegatesBatch(representatives.map(delegationTo));
+
+    for (const representative of representatives) {
+      const account = await program.account.delegation.fetch(
+        delegationTo(representative)
+      );
+      assert.ok(account.master.equals(master.publicKey));
+      assert.ok(account.representative.equals(representative));
+      assert.equal(account.permissions.toString(), PERMISSION_CLAIM.toString());
+      assert.equal(account.label, "bot fleet");
+      assert.isFalse(account.authorised);
+    }
+    assert.equal(
+      (await program.account.masterIndex.fetch(masterIndexOf(master.publicKey)))
+        .delegations.length,
+      3
+    );
+  });
+
+  it("Cancel several delegations at once", async () => {
+    const master = Keypair.generate();
+    const hacker = Keypair.generate();
+    const representatives = [0, 1, 2].map(() => Keypair.generate());
+
+    await airdrop(master.publicKey);
+
+    const delegations = representatives.map((representative) =>
+      delegationOf(master.publicKey, representative.publicKey)
+    );
+
+    for (const representative of representatives) {
+      await initializeDelegate(master, representative.publicKey);
+    }
+
+    const cancelDelegatesBatch = (
+      signer: Keypair,
+      entries: { delegation: PublicKey; index: PublicKey }[]
+    ) =>
+      program.methods
+        .cancelDelegatesBatch()
+        .accounts({
+          signer: signer.publicKey,
+          masterIndex: masterIndexOf(signer.publicKey),
+          representativeIndex: representativeIndexOf(signer.publicKey),
+        })
+        .remainingAccounts(
+          entries.flatMap(({ delegation, index }) => [
+            { pubkey: delegation, isSigner: false, isWritable: true },
+            { pubkey: master.publicKey, isSigner: false, isWritable: true },
+            { pubkey: index, isSigner: false, isWritable: true },
+          ])
+        )
+        .signers([signer])
+        .rpc();
+
+    try {
+      await cancelDelegatesBatch(hacker, [
+        { delegation: delegations[0], index: masterIndexOf(master.publicKey) },
+      ]);
+      assert(false);
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "WrongSigner");
+    }
+
+    await cancelDelegatesBatch(representatives[0], [
+      { delegation: delegations[0], index: masterIndexOf(master.publicKey) },
+    ]);
+    await cancelDelegatesBatch(
+      master,
+      representatives.slice(1).map((representative, i) => ({
+        delegation: delegations[i + 1],
+        index: representativeIndexOf(representative.publicKey),
+      }))
+    );
+
+    for (const delegation of delegations) {
+      assert.isNull(await connection.getAccountInfo(delegation));
+    }
+    assert.equal(
+      (await program.account.masterIndex.fetch(masterIndexOf(master.publicKey)))
+        .delegations.length,
+      0
+    );
+  });
+
+  it("Delegate to a session key closed once expired", async () => {
+    const master = Keypair.generate();
+    const sessionKey = Keypair.generate();
+
+    await airdrop(master.publicKey);
+
+    const delegation = delegationOf(master.publicKey, sessionKey.publicKey);
+    const now = await clockTime();
+
+    const initializeSessionDelegate = (validUntil: number) =>
+      program.methods
+        .initializeSessionDelegate(
+          terms({
+            permissions: PERMISSION_CLAIM,
+            validUntil: new anchor.BN(validUntil),
+          })
+        )
+        .accounts({
+          master: master.publicKey,
+          sessionKey: sessionKey.publicKey,
+          delegation,
+          masterIndex: masterIndexOf(master.publicKey),
+          masterSettings: masterSettingsOf(master.publicKey),
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([master])
+        .rpc();
+
+    try {
+      await initializeSessionDelegate(now + 8 * 24 * 60 * 60);
+      assert(false);
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "InvalidSessionDuration");
+    }
+
+    // long enough for the checks below to run before the session expires
+    const validUntil = now + 10;
+    await initializeSessionDelegate(validUntil);
+    const account = await program.account.delegation.fetch(delegation);
+    assert.isTrue(account.authorised);
+    assert.isTrue(account.session);
+
+    await program.methods
+      .assertAuthorized(null, null, PERMISSION_CLAIM)
+      .accounts({
+        master: master.publicKey,
+        representative: sessionKey.publicKey,
+        delegation,
+        masterSettings: masterSettingsOf(master.publicKey),
+      })
+      .rpc();
+
+    const closeExpiredSession = (rentRecipient = master.publicKey) =>
+      program.methods
+        .closeExpiredSession()
+        .accounts({
+          master: master.publicKey,
+          delegation,
+          rentRecipient,
+          masterIndex: masterIndexOf(master.publicKey),
+        })
+        .rpc();
+
+    try {
+      await closeExpiredSession();
+      assert(false);
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "SessionNotExpired");
+    }
+
+    await waitForClock(validUntil);
+
+    try {
+      await closeExpiredSession(Keypair.generate().publicKey);
+      assert(false);
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "WrongRentRecipient");
+    }
+
+    const balance = await connection.getBalance(master.publicKey);
+    await closeExpiredSession();
+    assert.isNull(await connection.getAccountInfo(delegation));
+    assert.isAbove(await connection.getBalance(master.publicKey), balance);
+  });
+
+  it("Role shared by delegations applies its permissions", async () => {
+    const master = Keypair.generate();
+    const representative = Keypair.generate();
+    const PERMISSION_WITHDRAW = new anchor.BN(1 << 4);
+
+    await airdrop(master.publicKey);
+
+    const delegation = delegationOf(master.publicKey, representative.publicKey);
+    const role = roleOf(master.publicKey, "operator");
+
+    await program.methods
+      .createRole("operator", PERMISSION_CLAIM, [])
+      .accounts({
+        authority: master.publicKey,
+        role,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([master])
+      .rpc();
+
+    await initializeDelegate(master, representative.publicKey);
+    await confirmDelegate(representative, delegation);
+
+    const stranger = Keypair.generate();
+    await airdrop(stranger.publicKey);
+    const strangerRole = roleOf(stranger.publicKey, "operator");
+    await program.methods
+      .createRole("operator", PERMISSION_ALL, [])
+      .accounts({
+        authority: stranger.publicKey,
+        role: strangerRole,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([stranger])
+      .rpc();
+
+    const assignRole = (role: PublicKey, protocolRole = false) =>
+      program.methods
+        .assignRole(protocolRole)
+        .accounts({ master: master.publicKey, delegation, role })
+        .signers([master])
+        .rpc();
+
+    try {
+      await assignRole(strangerRole);
+      assert(false);
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "WrongRoleAuthority");
+    }
+
+    // the roles of a protocol are only assigned with the master's consent
+    await assignRole(strangerRole, true);
+    assert.isTrue(
+      (await program.account.delegation.fetch(delegation)).role.equals(
+        strangerRole
+      )
+    );
+
+    await assignRole(role);
+    assert.isTrue(
+      (await program.account.delegation.fetch(delegation)).role.equals(role)
+    );
+
+    const assertAuthorized = (scope: anchor.BN, withRole = true) =>
+      program.methods
+        .assertAuthorized(null, null, scope)
+        .accounts({
+          master: master.publicKey,
+          representative: representative.publicKey,
+          delegation,
+          masterSettings: masterSettingsOf(master.publicKey),
+        })
+        .remainingAccounts(
+          withRole
+            ? [{ pubkey: role, isSigner: false, isWritable: false }]
+            : []
+        )
+        .rpc();
+
+    await assertAuthorized(PERMISSION_CLAIM);
+
+    try {
+      await assertAuthorized(PERMISSION_WITHDRAW);
+      assert(false);
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "MissingPermission");
+    }
+
+    try {
+      await assertAuthorized(PERMISSION_CLAIM, false);
+      assert(false);
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "MissingRoleAccount");
+    }
+
+    await program.methods
+      .updateRole(PERMISSION_CLAIM.or(PERMISSION_WITHDRAW), [])
+      .accounts({ authority: master.publicKey, role })
+      .signers([master])
+      .rpc();
+    await assertAuthorized(PERMISSION_WITHDRAW);
+
+    await program.methods
+      .unassignRole()
+      .accounts({ master: master.publicKey, delegation })
+      .signers([master])
+      .rpc();
+    await assertAuthorized(PERMISSION_ALL, false);
+
+    await program.methods
+      .closeRole()
+      .accounts({ authority: master.publicKey, role })
+      .signers([master])
+      .rpc();
+    assert.isNull(await connection.getAccountInfo(role));
+  });
+
+  it("Role names longer than MAX_ROLE_NAME_LEN are rejected", async () => {
+    const master = Keypair.generate();
+    const name = "a".repeat(33);
+
+    await airdrop(master.publicKey);
+
+    try {
+      await program.methods
+        .createRole(name, PERMISSION_CLAIM, [])
+        .accounts({
+          authority: master.publicKey,
+          role: roleOf(master.publicKey, name),
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([master])
+        .rpc();
+      assert(false);
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "RoleNameTooLong");
+    }
+  });
 });